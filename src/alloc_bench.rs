@@ -0,0 +1,332 @@
+//! Allocation benchmarks of the slice import, only built with `--features alloc-bench` since the
+//! counting allocator slows down every other test. Set `EXTYS_BENCH_SLICES` to a slice directory to
+//! measure a real corpus instead of the synthetic one.
+
+use crate::cache::ImportCache;
+use crate::decontam::SliceDenyList;
+use crate::input;
+use crate::slice_structs::{FullSlice, Interner, ObjSlice};
+use crate::spill::SpillWriter;
+use crate::stats::{FilterStats, RunReport};
+use crate::utils::{self, extract_scope_name};
+use clap::Parser;
+use rand::Rng;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::cmp::max;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// Counts allocations of the current thread, so that concurrently running tests do not interfere
+struct CountingAllocator;
+
+thread_local! {
+    static NUM_ALLOCS: Cell<usize> = const { Cell::new(0) };
+    static CURR_BYTES: Cell<usize> = const { Cell::new(0) };
+    static PEAK_BYTES: Cell<usize> = const { Cell::new(0) };
+}
+
+fn track_alloc(size: usize) {
+    let _ = NUM_ALLOCS.try_with(|n| n.set(n.get() + 1));
+    let _ = CURR_BYTES.try_with(|curr| {
+        curr.set(curr.get() + size);
+        let _ = PEAK_BYTES.try_with(|peak| peak.set(max(peak.get(), curr.get())));
+    });
+}
+
+fn track_dealloc(size: usize) {
+    let _ = CURR_BYTES.try_with(|curr| curr.set(curr.get().saturating_sub(size)));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        track_alloc(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        track_dealloc(layout.size());
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        track_dealloc(layout.size());
+        track_alloc(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Returns the number of allocations and the peak heap usage in bytes of `f`
+fn measure_allocations<R>(f: impl FnOnce() -> R) -> (R, usize, usize) {
+    NUM_ALLOCS.with(|n| n.set(0));
+    let base = CURR_BYTES.with(|c| c.get());
+    PEAK_BYTES.with(|p| p.set(base));
+
+    let res = f();
+
+    let num_allocs = NUM_ALLOCS.with(|n| n.get());
+    let peak = PEAK_BYTES.with(|p| p.get()) - base;
+    (res, num_allocs, peak)
+}
+
+/// Slice structs as they were before borrowing from the input buffer
+mod owned {
+    #![allow(dead_code)]
+
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct FullSlice {
+        pub object_slices: HashMap<String, Vec<JsonObjSlice>>,
+        pub user_defined_types: Vec<serde_json::Value>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct JsonObjSlice {
+        pub target_obj: TargetObj,
+        pub defined_by: serde_json::Value,
+        pub invoked_calls: Vec<Call>,
+        pub arg_to_calls: Vec<(Call, i32)>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct TargetObj {
+        pub name: String,
+        pub type_full_name: String,
+        pub literal: bool,
+    }
+
+    #[derive(Clone, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Call {
+        pub receiver: Option<String>,
+        pub call_name: String,
+        pub param_types: Vec<serde_json::Value>,
+        pub return_type: String,
+    }
+
+    pub struct ObjSlice {
+        pub name: String,
+        pub scope: String,
+        pub type_name: String,
+        pub invoked_calls: Vec<Call>,
+        pub arg_to_calls: Vec<(Call, i32)>,
+    }
+}
+
+/// Synthetic slice files with a realistic amount of repeated call and type names
+fn generate_corpus(num_files: usize, objs_per_scope: usize) -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    let call_names = [
+        "push",
+        "then",
+        "toString",
+        "<operator>.assignment",
+        "console.log",
+        "getElementById",
+        "JSON.stringify",
+        "map",
+    ];
+    let type_names = [
+        "__ecma.String",
+        "import(\\\"./models/user\\\").User",
+        "Promise<string>",
+        "number",
+    ];
+
+    let call = |name: &str| {
+        format!(
+            r#"{{"receiver":"this","callName":"{}","paramTypes":["ANY"],"returnType":"ANY"}}"#,
+            name
+        )
+    };
+
+    (0..num_files)
+        .map(|f| {
+            let scopes: Vec<String> = (0..4)
+                .map(|s| {
+                    let objs: Vec<String> = (0..objs_per_scope)
+                        .map(|o| {
+                            let calls: Vec<String> = (0..rng.gen_range(1..8))
+                                .map(|_| call(call_names[rng.gen_range(0..call_names.len())]))
+                                .collect();
+                            let arg_tos: Vec<String> = (0..rng.gen_range(0..4))
+                                .map(|_| {
+                                    format!(
+                                        "[{},1]",
+                                        call(call_names[rng.gen_range(0..call_names.len())])
+                                    )
+                                })
+                                .collect();
+                            format!(
+                                r#"{{"targetObj":{{"name":"obj{}","typeFullName":"{}","literal":false}},"definedBy":null,"invokedCalls":[{}],"argToCalls":[{}]}}"#,
+                                o,
+                                type_names[rng.gen_range(0..type_names.len())],
+                                calls.join(","),
+                                arg_tos.join(",")
+                            )
+                        })
+                        .collect();
+                    format!(
+                        r#""src/project/file{}.ts::program:func{}":[{}]"#,
+                        f,
+                        s,
+                        objs.join(",")
+                    )
+                })
+                .collect();
+            format!(
+                r#"{{"objectSlices":{{{}}},"userDefinedTypes":[]}}"#,
+                scopes.join(",")
+            )
+        })
+        .collect()
+}
+
+/// Directory of the benchmarked corpus, a synthetic one is written unless `EXTYS_BENCH_SLICES` is set
+fn corpus_dir() -> PathBuf {
+    if let Ok(dir) = std::env::var("EXTYS_BENCH_SLICES") {
+        return PathBuf::from(dir);
+    }
+
+    // the synthetic corpus is random but only written once, later runs reuse it
+    let slices = std::env::temp_dir().join("extys-bench-corpus");
+    if !slices.exists() {
+        for (i, c) in generate_corpus(4096, 64).iter().enumerate() {
+            let project = slices.join(format!("project{}", i % 16));
+            fs::create_dir_all(&project).unwrap();
+            fs::write(project.join(format!("file{}.json", i)), c).unwrap();
+        }
+    }
+    slices
+}
+
+/// Decompressed content of every slice file of the corpus
+fn read_corpus(dir: &std::path::Path) -> Vec<String> {
+    let mut corpus = Vec::new();
+    for (path, kind) in input::find_slice_inputs(&dir.display().to_string()) {
+        input::for_each_slice_file(&path, kind, |_, c| corpus.push(c.unwrap().to_owned()))
+            .expect("Failed to read benchmark corpus");
+    }
+    corpus
+}
+
+#[test]
+pub fn benchmark_slice_deserialization() {
+    let corpus = read_corpus(&corpus_dir());
+    let parser = utils::Parser::new(&None);
+
+    let t0 = Instant::now();
+    let (owned_slices, owned_allocs, owned_peak) = measure_allocations(|| {
+        let mut slices = Vec::new();
+        for c in &corpus {
+            let full: owned::FullSlice = serde_json::from_str(c).unwrap();
+            for (scope, vars) in full.object_slices {
+                for obj in vars {
+                    slices.push(owned::ObjSlice {
+                        name: obj.target_obj.name,
+                        scope: extract_scope_name(parser.lang, &scope),
+                        type_name: obj.target_obj.type_full_name,
+                        invoked_calls: obj.invoked_calls,
+                        arg_to_calls: obj.arg_to_calls,
+                    });
+                }
+            }
+        }
+        slices
+    });
+    let owned_time = t0.elapsed().as_millis();
+
+    // same as `import_slices`: borrowed structs and one interner per slice file
+    let t0 = Instant::now();
+    let (borrowed_slices, borrowed_allocs, borrowed_peak) = measure_allocations(|| {
+        let mut slices = Vec::new();
+        for c in &corpus {
+            let full: FullSlice = serde_json::from_str(c).unwrap();
+            let mut interner = Interner::default();
+            for (scope, vars) in full.object_slices {
+                let scope = interner.intern(&extract_scope_name(parser.lang, &scope));
+                for obj in vars {
+                    let type_name = obj.target_obj.type_full_name.to_string();
+                    slices.push(ObjSlice::new(
+                        &obj,
+                        scope.clone(),
+                        &type_name,
+                        &mut interner,
+                    ));
+                }
+            }
+        }
+        slices
+    });
+    let borrowed_time = t0.elapsed().as_millis();
+
+    assert_eq!(owned_slices.len(), borrowed_slices.len());
+    println!(
+        "{} slice files\nowned:    {:9} allocations, {:7} KiB peak, {:5} ms\nborrowed: {:9} allocations, {:7} KiB peak, {:5} ms",
+        corpus.len(),
+        owned_allocs,
+        owned_peak / 1024,
+        owned_time,
+        borrowed_allocs,
+        borrowed_peak / 1024,
+        borrowed_time
+    );
+
+    assert!(borrowed_allocs < owned_allocs);
+    assert!(borrowed_peak < owned_peak);
+}
+
+#[test]
+pub fn benchmark_import() {
+    let slices = corpus_dir();
+    let output_dir = std::env::temp_dir().join(format!("extys-bench-out-{}", std::process::id()));
+    fs::create_dir_all(&output_dir).unwrap();
+
+    let args = crate::Args::parse_from([
+        "extys",
+        "-s",
+        slices.to_str().unwrap(),
+        "-o",
+        output_dir.to_str().unwrap(),
+    ]);
+    let parser = utils::Parser::new(&args.language);
+    let mut spill = SpillWriter::create(
+        &output_dir.join(".extys_spill"),
+        args.spill_partitions,
+        args.spill_partition_mb * 1024 * 1024,
+    );
+    let mut deny_list = SliceDenyList::new(&args.exclude_slices);
+    let mut filter_stats = FilterStats::new(0);
+    let mut report = RunReport::new(&args);
+
+    let t0 = Instant::now();
+    let (_, allocs, peak) = measure_allocations(|| {
+        crate::import_slices(
+            &args,
+            &parser,
+            &mut spill,
+            None::<&mut ImportCache>,
+            &mut deny_list,
+            &mut filter_stats,
+            &mut report,
+        )
+    });
+    println!(
+        "import_slices: {:9} allocations, {:7} KiB peak, {:5} ms for {} slice files",
+        allocs,
+        peak / 1024,
+        t0.elapsed().as_millis(),
+        report.import.num_files
+    );
+    spill.finish();
+    let _ = fs::remove_dir_all(&output_dir);
+}
@@ -0,0 +1,415 @@
+use crate::decontam::SliceDenyList;
+use crate::input::{self, InputKind};
+use crate::slice_structs::{NamesSlice, ObjSlice};
+use crate::utils::{self, Language};
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// Neutral name replacing masked variable names
+pub const VARIABLE_PLACEHOLDER: &str = "VAR";
+/// Neutral name replacing the file of masked scopes
+pub const FILE_PLACEHOLDER: &str = "FILE";
+
+/// Identifiers that are replaced with placeholders in the features
+#[derive(clap::ValueEnum, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anonymize {
+    /// Replace the variable name and the names of its siblings with `VAR`
+    Variable,
+    /// Replace the file with `FILE` and function names with `FUNC_n`
+    Scope,
+    /// Replace project-specific functions and types in call names with `FUNC_n` and `TYPE_n`
+    Calls,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Func,
+    Type,
+}
+
+impl Kind {
+    fn prefix(self) -> &'static str {
+        match self {
+            Kind::Func => "FUNC",
+            Kind::Type => "TYPE",
+        }
+    }
+}
+
+/// Last segment of a qualified name, e.g. `getName` of `models/user.ts::program:User.getName`
+fn identifier(name: &str) -> &str {
+    name.rsplit(['.', ':']).next().unwrap_or(name)
+}
+
+/// Name of a declared procedure without its signature
+fn procedure_name(name: &str, lang: Language) -> &str {
+    let name = name.split('(').next().unwrap_or(name);
+    match lang {
+        // `pkg.Type.method:returnType(params)`
+        Language::Java => identifier(name.split(':').next().unwrap_or(name)),
+        _ => identifier(name),
+    }
+}
+
+/// Identifiers defined in a project: its user-defined types, the methods of its types and its
+/// free functions, collected over all slice files of the project. Methods are only known together
+/// with their type, so library methods that share a name with a project method are not matched.
+#[derive(Default)]
+pub struct ProjectNames {
+    types: HashSet<String>,
+    /// Methods as `Type.method`
+    methods: HashSet<String>,
+    /// Functions that do not belong to a type
+    functions: HashSet<String>,
+}
+
+impl ProjectNames {
+    /// Adds the functions of the scopes and the user-defined types of a slice file
+    pub fn add(&mut self, slice: &NamesSlice, lang: Language) {
+        for scope in slice.object_slices.keys() {
+            let scope = utils::extract_scope_name(lang, scope);
+            let mut components = scope.split("::");
+            // the scope of Java methods starts with their class, other scopes with a file
+            let class = match lang {
+                Language::Java => components.next().map(identifier),
+                _ => {
+                    components.next();
+                    None
+                }
+            };
+            let segments: Vec<&str> = class
+                .into_iter()
+                .chain(components.flat_map(|c| c.split(['.', ':'])))
+                .filter(|s| !s.is_empty() && !s.starts_with('<'))
+                .collect();
+
+            match segments.as_slice() {
+                [] => {}
+                [function] => {
+                    self.functions.insert(function.to_string());
+                }
+                [.., owner, method] => {
+                    self.methods.insert(format!("{}.{}", owner, method));
+                }
+            }
+            if let Some(class) = class {
+                self.insert_type(class);
+            }
+        }
+
+        for udt in &slice.user_defined_types {
+            let owner = identifier(&udt.name);
+            self.insert_type(owner);
+            for procedure in &udt.procedures {
+                let method = procedure_name(&procedure.call_name, lang);
+                if !method.is_empty() && !method.starts_with('<') {
+                    self.methods.insert(format!("{}.{}", owner, method));
+                }
+            }
+        }
+    }
+
+    fn insert_type(&mut self, name: &str) {
+        // synthetic names such as `<init>` or `<module>` are not project-specific
+        if !name.is_empty() && !name.starts_with('<') {
+            self.types.insert(name.to_owned());
+        }
+    }
+
+    fn is_type(&self, name: &str) -> bool {
+        self.types.contains(name)
+    }
+
+    /// Kind of the `i`-th segment of a dotted call name, `None` if it is not defined in the
+    /// project. Methods have to be qualified by their type, free functions must stand alone.
+    fn call_segment(&self, segments: &[&str], i: usize) -> Option<Kind> {
+        let method = i > 0
+            && self
+                .methods
+                .contains(&format!("{}.{}", segments[i - 1], segments[i]));
+        let function = segments.len() == 1 && self.functions.contains(segments[i]);
+
+        if self.is_type(segments[i]) {
+            Some(Kind::Type)
+        } else if method || function {
+            Some(Kind::Func)
+        } else {
+            None
+        }
+    }
+
+    /// Digest of all names, results that depend on the names are only valid for the same digest
+    pub fn digest(&self) -> String {
+        let mut hasher = blake3::Hasher::new();
+        for (tag, names) in [
+            ("type", &self.types),
+            ("method", &self.methods),
+            ("function", &self.functions),
+        ] {
+            for name in names.iter().sorted() {
+                hasher.update(tag.as_bytes());
+                hasher.update(name.as_bytes());
+                hasher.update(b"\n");
+            }
+        }
+        hasher.finalize().to_hex().to_string()
+    }
+}
+
+/// Collects the names of every project in a pass over all inputs ahead of the import, so that
+/// names defined in one slice file are known when anonymizing the others. Files that cannot be
+/// read or parsed are skipped here and reported by the import.
+pub fn collect_project_names(
+    slices_dir: &str,
+    inputs: &[(PathBuf, InputKind)],
+    deny_list: &SliceDenyList,
+    lang: Language,
+) -> HashMap<String, ProjectNames> {
+    let mut projects: HashMap<String, ProjectNames> = HashMap::new();
+
+    for (path, kind) in inputs {
+        if deny_list.matches(&path.display().to_string()) {
+            continue;
+        }
+
+        let _ = input::for_each_slice_file(path, *kind, |source, c| {
+            if kind.is_archive() && deny_list.matches(source) {
+                return;
+            }
+            if let Some(slice) = c
+                .ok()
+                .and_then(|c| serde_json::from_str::<NamesSlice>(c).ok())
+            {
+                projects
+                    .entry(input::project_name(slices_dir, source))
+                    .or_default()
+                    .add(&slice, lang);
+            }
+        });
+    }
+
+    projects
+}
+
+/// Replaces identifiers of one object with placeholders. Placeholders are numbered per object, so
+/// the same function is always called `FUNC_1` within a sample, whether it is the scope or called.
+pub struct Anonymizer<'a> {
+    modes: &'a [Anonymize],
+    lang: Language,
+    names: &'a ProjectNames,
+    placeholders: HashMap<String, String>,
+    num_funcs: usize,
+    num_types: usize,
+}
+
+impl<'a> Anonymizer<'a> {
+    pub fn new(modes: &'a [Anonymize], lang: Language, names: &'a ProjectNames) -> Self {
+        Anonymizer {
+            modes,
+            lang,
+            names,
+            placeholders: HashMap::new(),
+            num_funcs: 0,
+            num_types: 0,
+        }
+    }
+
+    fn placeholder(&mut self, name: &str, kind: Kind) -> String {
+        if let Some(placeholder) = self.placeholders.get(name) {
+            return placeholder.to_owned();
+        }
+
+        let n = match kind {
+            Kind::Func => &mut self.num_funcs,
+            Kind::Type => &mut self.num_types,
+        };
+        *n += 1;
+        let placeholder = format!("{}_{}", kind.prefix(), n);
+        self.placeholders
+            .insert(name.to_owned(), placeholder.to_owned());
+        placeholder
+    }
+
+    fn scope(&mut self, scope: &str) -> String {
+        scope
+            .split("::")
+            .enumerate()
+            .map(|(i, component)| match (i, self.lang) {
+                (0, Language::Java) => self.placeholder(identifier(component), Kind::Type),
+                (0, _) => FILE_PLACEHOLDER.to_owned(),
+                _ => {
+                    let name = identifier(component);
+                    let kind = if self.names.is_type(name) {
+                        Kind::Type
+                    } else {
+                        Kind::Func
+                    };
+                    self.placeholder(name, kind)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("::")
+    }
+
+    fn call(&mut self, call: &str) -> String {
+        let segments: Vec<&str> = call.split('.').collect();
+        (0..segments.len())
+            .map(|i| match self.names.call_segment(&segments, i) {
+                Some(kind) => self.placeholder(segments[i], kind),
+                None => segments[i].to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    /// Anonymizes the name and scope of the object and its usages, as selected by the modes. The
+    /// defining expression is source code that may contain any of these names, so it is dropped.
+    pub fn apply(&mut self, slice: &mut ObjSlice, calls: &mut [String], arg_tos: &mut [String]) {
+        if !self.modes.is_empty() {
+            slice.defined_by = None;
+        }
+        if self.modes.contains(&Anonymize::Variable) {
+            slice.name = VARIABLE_PLACEHOLDER.to_owned();
+            // known types of the siblings stay
+            for sibling in &mut slice.siblings {
+                *sibling = match sibling.split_once(": ") {
+                    Some((_, type_name)) => format!("{}: {}", VARIABLE_PLACEHOLDER, type_name),
+                    None => VARIABLE_PLACEHOLDER.to_owned(),
+                };
+            }
+        }
+        if self.modes.contains(&Anonymize::Scope) {
+            slice.scope = Rc::from(self.scope(&slice.scope));
+        }
+        if self.modes.contains(&Anonymize::Calls) {
+            for call in calls.iter_mut().chain(arg_tos.iter_mut()) {
+                *call = self.call(call);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(slices: &[&str], lang: Language) -> ProjectNames {
+        let mut names = ProjectNames::default();
+        for slice in slices {
+            names.add(&serde_json::from_str(slice).unwrap(), lang);
+        }
+        names
+    }
+
+    fn anonymize_calls(names: &ProjectNames, lang: Language, calls: &[&str]) -> Vec<String> {
+        let mut anonymizer = Anonymizer::new(&[Anonymize::Calls], lang, names);
+        calls.iter().map(|c| anonymizer.call(c)).collect()
+    }
+
+    #[test]
+    fn names_are_collected_across_files() {
+        let names = names(
+            &[
+                r#"{"objectSlices": {"src/util.ts::program:helper": []},
+                    "userDefinedTypes": [{"name": "src/user.ts::program:User",
+                                          "procedures": [{"callName": "get"}]}]}"#,
+                r#"{"objectSlices": {"src/main.ts::program:main": []}, "userDefinedTypes": []}"#,
+            ],
+            Language::TypeScript,
+        );
+
+        assert_eq!(
+            anonymize_calls(
+                &names,
+                Language::TypeScript,
+                &["User.get", "helper", "main", "map.get", "get", "toString"]
+            ),
+            vec![
+                "TYPE_1.FUNC_1",
+                "FUNC_2",
+                "FUNC_3",
+                "map.get",
+                "get",
+                "toString"
+            ]
+        );
+    }
+
+    #[test]
+    fn java_methods_are_matched_by_class() {
+        let names = names(
+            &[
+                r#"{"objectSlices": {"com.acme.model.User.getName:java.lang.String()": []},
+                    "userDefinedTypes": []}"#,
+                r#"{"objectSlices": {"com.acme.Service.run:void(int)": []},
+                    "userDefinedTypes": [{"name": "com.acme.Repo",
+                                          "procedures": [{"callName": "com.acme.Repo.add:boolean(java.lang.Object)"}]}]}"#,
+            ],
+            Language::Java,
+        );
+
+        assert_eq!(
+            anonymize_calls(
+                &names,
+                Language::Java,
+                &[
+                    "com.acme.model.User.getName",
+                    "com.acme.Repo.add",
+                    "java.util.List.add",
+                    "java.lang.Object.toString",
+                ]
+            ),
+            vec![
+                "com.acme.model.TYPE_1.FUNC_1",
+                "com.acme.TYPE_2.FUNC_2",
+                "java.util.List.add",
+                "java.lang.Object.toString",
+            ]
+        );
+    }
+
+    #[test]
+    fn defining_expression_is_dropped() {
+        let names = ProjectNames::default();
+        let mut slice = ObjSlice {
+            name: "user".to_string(),
+            scope: Rc::from("src/app.ts::program:load"),
+            type_name: Rc::from("User"),
+            invoked_calls: vec![],
+            arg_to_calls: vec![],
+            return_types: vec![],
+            defined_by: Some("user = loadUser(id)".to_string()),
+            literal: false,
+            literal_kind: None,
+            siblings: vec![],
+        };
+
+        Anonymizer::new(&[Anonymize::Variable], Language::TypeScript, &names).apply(
+            &mut slice,
+            &mut [],
+            &mut [],
+        );
+        assert_eq!(slice.name, VARIABLE_PLACEHOLDER);
+        assert_eq!(slice.defined_by, None);
+    }
+
+    #[test]
+    fn digest_follows_the_names() {
+        let slice = r#"{"objectSlices": {"src/a.ts::program:helper": []}, "userDefinedTypes": []}"#;
+        let other = r#"{"objectSlices": {"src/a.ts::program:other": []}, "userDefinedTypes": []}"#;
+        let lang = Language::TypeScript;
+
+        assert_eq!(
+            names(&[slice], lang).digest(),
+            names(&[slice, slice], lang).digest()
+        );
+        assert_ne!(
+            names(&[slice], lang).digest(),
+            names(&[slice, other], lang).digest()
+        );
+    }
+}
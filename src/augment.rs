@@ -0,0 +1,453 @@
+use crate::anonymize::VARIABLE_PLACEHOLDER;
+use crate::slice_structs::{FeatureRecord, FeatureValues, Sample};
+use crate::utils::{self, CallOrder};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+/// Attempts per requested sample before giving up on a class with too few distinct variants
+const MAX_ATTEMPTS: usize = 10;
+
+/// Split of the augmented samples, only training samples are augmented
+pub const TRAIN_SPLIT: usize = 0;
+
+/// Transformation that generated an augmented sample
+#[derive(
+    clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum AugmentMethod {
+    /// Shuffle the order of calls and argument-to entries
+    Shuffle,
+    /// Drop random usages, keeping at least the lower usage bound
+    DropUsages,
+    /// Replace the variable name with a placeholder
+    MaskVariable,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct AugmentStats {
+    pub target: usize,
+    pub seed: u64,
+    /// Generated samples per class
+    pub classes: BTreeMap<String, usize>,
+    pub methods: BTreeMap<AugmentMethod, usize>,
+}
+
+/// Generates extra training samples for classes with fewer than `target` samples
+pub struct Augmenter {
+    target: usize,
+    seed: u64,
+    methods: Vec<AugmentMethod>,
+    lower_usage_bound: usize,
+    call_order: CallOrder,
+    /// Training samples of the classes that may need augmentation
+    seeds: HashMap<String, Vec<Sample>>,
+    stats: AugmentStats,
+}
+
+impl Augmenter {
+    pub fn new(
+        target: usize,
+        seed: u64,
+        methods: &[AugmentMethod],
+        lower_usage_bound: usize,
+        call_order: CallOrder,
+    ) -> Self {
+        let mut methods = methods.to_vec();
+        methods.sort();
+        methods.dedup();
+
+        Augmenter {
+            target,
+            seed,
+            methods,
+            lower_usage_bound,
+            call_order,
+            seeds: HashMap::new(),
+            stats: AugmentStats {
+                target,
+                seed,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Keeps a training sample as a seed. A class with `target` training samples never needs
+    /// augmentation, so at most that many seeds are kept per class.
+    pub fn observe(&mut self, sample: &Sample, split: usize) {
+        // no variant of an evaluation sample may leak into the training split
+        if split != TRAIN_SPLIT {
+            return;
+        }
+
+        let seeds = self.seeds.entry(sample.label.to_owned()).or_default();
+        if seeds.len() < self.target {
+            seeds.push(sample.clone());
+        }
+    }
+
+    /// Generates the augmented samples of all classes, given the final number of samples per class.
+    /// `render` turns the values of an augmented sample into its feature.
+    pub fn generate(
+        &mut self,
+        final_counts: &HashMap<String, usize>,
+        render: impl Fn(&FeatureValues) -> String,
+    ) -> Vec<Sample> {
+        let mut labels: Vec<&String> = self.seeds.keys().collect();
+        labels.sort();
+
+        let mut augmented = Vec::new();
+        for label in labels {
+            let needed = self
+                .target
+                .saturating_sub(*final_counts.get(label).unwrap_or(&0));
+            if needed == 0 {
+                continue;
+            }
+
+            // seeds are collected in hash order, sort them so a seed reproduces the same samples
+            let mut seeds = self.seeds[label].clone();
+            seeds.sort_by(|a, b| (&a.feature, a.num_usages).cmp(&(&b.feature, b.num_usages)));
+            let mut seen: HashSet<String> = seeds.iter().map(|s| s.feature.to_owned()).collect();
+
+            let mut hasher = DefaultHasher::new();
+            (self.seed, label).hash(&mut hasher);
+            let mut rng = StdRng::seed_from_u64(hasher.finish());
+
+            let mut generated = 0;
+            for _ in 0..needed * MAX_ATTEMPTS {
+                if generated == needed {
+                    break;
+                }
+
+                let seed = seeds.choose(&mut rng).unwrap();
+                let method = *self.methods.choose(&mut rng).unwrap();
+                if let Some(sample) = self.apply(seed, method, &mut rng, &render) {
+                    if seen.insert(sample.feature.to_owned()) {
+                        *self.stats.methods.entry(method).or_insert(0) += 1;
+                        augmented.push(sample);
+                        generated += 1;
+                    }
+                }
+            }
+
+            if generated > 0 {
+                self.stats.classes.insert(label.to_owned(), generated);
+            }
+        }
+
+        augmented
+    }
+
+    /// Applies a transformation to the values of a sample and renders its feature again, `None`
+    /// if the transformation cannot change the sample
+    fn apply(
+        &self,
+        sample: &Sample,
+        method: AugmentMethod,
+        rng: &mut StdRng,
+        render: &impl Fn(&FeatureValues) -> String,
+    ) -> Option<Sample> {
+        let mut values = sample.values.clone();
+
+        match method {
+            AugmentMethod::Shuffle => {
+                if values.calls.len() < 2 && values.arg_tos.len() < 2 {
+                    return None;
+                }
+                values.calls.shuffle(rng);
+                values.arg_tos.shuffle(rng);
+            }
+            AugmentMethod::DropUsages => {
+                let total = values.calls.len() + values.arg_tos.len();
+                let min_keep = self.lower_usage_bound.max(1);
+                if total <= min_keep {
+                    return None;
+                }
+
+                // keep a random subset in the original order
+                let keep = rng.gen_range(min_keep..total);
+                let mut kept: Vec<usize> = (0..total).collect();
+                kept.shuffle(rng);
+                let kept: HashSet<usize> = kept.into_iter().take(keep).collect();
+
+                let num_calls = values.calls.len();
+                values.calls = std::mem::take(&mut values.calls)
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(i, _)| kept.contains(i))
+                    .map(|(_, c)| c)
+                    .collect();
+                values.arg_tos = std::mem::take(&mut values.arg_tos)
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(i, _)| kept.contains(&(num_calls + i)))
+                    .map(|(_, c)| c)
+                    .collect();
+            }
+            AugmentMethod::MaskVariable => {
+                if values.variable == VARIABLE_PLACEHOLDER {
+                    return None;
+                }
+                values.variable = VARIABLE_PLACEHOLDER.to_owned();
+            }
+        }
+
+        let record = sample.record.as_ref().map(|r| match method {
+            AugmentMethod::MaskVariable => FeatureRecord {
+                variable: VARIABLE_PLACEHOLDER.to_owned(),
+                ..r.clone()
+            },
+            _ => r.with_usages(&values.calls, &values.arg_tos, self.call_order),
+        });
+        let record = record.map(|r| match r.subtokens {
+            Some(_) => r.with_subtokens(self.call_order),
+            None => r,
+        });
+        Some(Sample {
+            feature: render(&values),
+            num_usages: utils::num_usages(&values.calls, &values.arg_tos, self.call_order),
+            values,
+            augmentation: Some(method),
+            record,
+            ..sample.clone()
+        })
+    }
+
+    pub fn stats(self) -> AugmentStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slice_structs::ArgTo;
+    use std::cell::Cell;
+
+    fn sample(label: &str, variable: &str, calls: &[&str], arg_tos: &[&str]) -> Sample {
+        let values = FeatureValues {
+            variable: variable.to_string(),
+            scope: "app.ts::load".to_string(),
+            calls: calls.iter().map(|c| c.to_string()).collect(),
+            arg_tos: arg_tos.iter().map(|c| c.to_string()).collect(),
+            ..Default::default()
+        };
+        Sample {
+            feature: render(&values),
+            num_usages: calls.len() + arg_tos.len(),
+            values,
+            label: label.to_string(),
+            project: "projA".to_string(),
+            source: "projA/slices.json".to_string(),
+            scope: "app.ts::load".to_string(),
+            variable: variable.to_string(),
+            split_strategy: None,
+            chunk: 0,
+            augmentation: None,
+            literal: false,
+            record: None,
+        }
+    }
+
+    fn render(values: &FeatureValues) -> String {
+        format!(
+            "{} ; {} ; {}",
+            values.variable,
+            values.calls.join(", "),
+            values.arg_tos.join(", ")
+        )
+    }
+
+    fn record(calls: &[&str], arg_tos: &[(&str, i32)]) -> FeatureRecord {
+        FeatureRecord {
+            variable: "name".to_string(),
+            scope: vec!["app.ts".to_string(), "load".to_string()],
+            calls: calls.iter().map(|c| c.to_string()).collect(),
+            arg_tos: arg_tos
+                .iter()
+                .map(|(call, position)| ArgTo {
+                    call: call.to_string(),
+                    position: *position,
+                })
+                .collect(),
+            defined_by: None,
+            literal: false,
+            literal_kind: None,
+            siblings: vec![],
+            subtokens: None,
+        }
+    }
+
+    fn arg_tos(record: &FeatureRecord) -> Vec<(&str, i32)> {
+        record
+            .arg_tos
+            .iter()
+            .map(|a| (a.call.as_str(), a.position))
+            .collect()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn generate(augmenter: &mut Augmenter, label: &str, count: usize) -> Vec<Sample> {
+        augmenter.generate(&HashMap::from([(label.to_string(), count)]), render)
+    }
+
+    #[test]
+    fn generation_gives_up_on_classes_without_variants() {
+        let mut augmenter =
+            Augmenter::new(10, 0, &[AugmentMethod::MaskVariable], 1, CallOrder::Set);
+        augmenter.observe(&sample("string", "name", &["trim"], &[]), TRAIN_SPLIT);
+
+        // masking yields a single variant, every further attempt renders a duplicate
+        let attempts = Cell::new(0);
+        let augmented = augmenter.generate(&HashMap::from([("string".to_string(), 1)]), |v| {
+            attempts.set(attempts.get() + 1);
+            render(v)
+        });
+
+        assert_eq!(augmented.len(), 1);
+        assert_eq!(attempts.get(), 9 * MAX_ATTEMPTS);
+        assert_eq!(augmenter.stats().classes["string"], 1);
+    }
+
+    #[test]
+    fn same_seed_gives_same_samples() {
+        let seeds = [
+            sample("string", "name", &["trim", "split", "slice"], &["log"]),
+            sample(
+                "string",
+                "title",
+                &["toUpperCase", "trim"],
+                &["print", "log"],
+            ),
+            sample("string", "path", &["join", "normalize", "resolve"], &[]),
+        ];
+        let features = |seed: u64, order: &[usize]| {
+            let methods = [AugmentMethod::Shuffle, AugmentMethod::DropUsages];
+            let mut augmenter = Augmenter::new(20, seed, &methods, 1, CallOrder::Set);
+            for &i in order {
+                augmenter.observe(&seeds[i], TRAIN_SPLIT);
+            }
+            generate(&mut augmenter, "string", 3)
+                .into_iter()
+                .map(|s| s.feature)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(!features(7, &[0, 1, 2]).is_empty());
+        assert_eq!(features(7, &[0, 1, 2]), features(7, &[2, 0, 1]));
+        assert_ne!(features(7, &[0, 1, 2]), features(8, &[0, 1, 2]));
+    }
+
+    #[test]
+    fn dropped_usages_keep_lower_bound() {
+        let calls = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let mut augmenter = Augmenter::new(50, 0, &[AugmentMethod::DropUsages], 3, CallOrder::Set);
+        augmenter.observe(&sample("string", "name", &calls, &["x", "y"]), TRAIN_SPLIT);
+
+        let augmented = generate(&mut augmenter, "string", 1);
+        assert!(!augmented.is_empty());
+        for sample in augmented {
+            assert!(sample.values.calls.len() + sample.values.arg_tos.len() >= 3);
+            assert!(sample.num_usages >= 3 && sample.num_usages < 10);
+        }
+    }
+
+    #[test]
+    fn masking_masks_record_variable() {
+        let mut seed = sample("string", "name", &["trim"], &[]);
+        seed.record = Some(record(&["trim"], &[]));
+        let mut augmenter = Augmenter::new(2, 0, &[AugmentMethod::MaskVariable], 1, CallOrder::Set);
+        augmenter.observe(&seed, TRAIN_SPLIT);
+
+        let augmented = generate(&mut augmenter, "string", 1);
+        assert_eq!(augmented.len(), 1);
+        assert_eq!(augmented[0].values.variable, VARIABLE_PLACEHOLDER);
+        assert_eq!(
+            augmented[0].record.as_ref().unwrap().variable,
+            VARIABLE_PLACEHOLDER
+        );
+    }
+
+    #[test]
+    fn only_training_samples_are_augmented() {
+        let methods = [AugmentMethod::Shuffle, AugmentMethod::MaskVariable];
+        let mut augmenter = Augmenter::new(10, 0, &methods, 1, CallOrder::Set);
+        augmenter.observe(&sample("string", "name", &["trim", "split"], &[]), 1);
+        augmenter.observe(&sample("number", "count", &["toFixed", "valueOf"], &[]), 2);
+        augmenter.observe(
+            &sample("boolean", "done", &["valueOf", "toString"], &[]),
+            TRAIN_SPLIT,
+        );
+
+        let final_counts = HashMap::from([
+            ("string".to_string(), 1),
+            ("number".to_string(), 1),
+            ("boolean".to_string(), 1),
+        ]);
+        let augmented = augmenter.generate(&final_counts, render);
+        assert!(!augmented.is_empty());
+        assert!(augmented.iter().all(|s| s.label == "boolean"));
+    }
+
+    #[test]
+    fn record_usages_keep_positions_of_sets() {
+        let record = record(&["trim"], &[("log", 0), ("log", 2), ("join", 1)]);
+
+        let reordered = record.with_usages(&names(&[]), &names(&["join", "log"]), CallOrder::Set);
+        assert!(reordered.calls.is_empty());
+        assert_eq!(
+            arg_tos(&reordered),
+            vec![("join", 1), ("log", 0), ("log", 2)]
+        );
+    }
+
+    #[test]
+    fn record_usages_keep_positions_of_sequences() {
+        // `log` is passed on twice in a row and once more after `join`
+        let sequence = record(
+            &["trim x2"],
+            &[
+                ("log x2", 0),
+                ("log x2", 2),
+                ("join", 1),
+                ("log", 0),
+                ("log", 2),
+            ],
+        );
+        let order = CallOrder::Sequence;
+
+        let reordered = sequence.with_usages(
+            &names(&["trim x2"]),
+            &names(&["log", "join", "log x2"]),
+            order,
+        );
+        assert_eq!(reordered.calls, names(&["trim x2"]));
+        assert_eq!(
+            arg_tos(&reordered),
+            vec![
+                ("log", 0),
+                ("log", 2),
+                ("join", 1),
+                ("log x2", 0),
+                ("log x2", 2)
+            ]
+        );
+
+        let dropped = sequence.with_usages(&names(&[]), &names(&["log x2"]), order);
+        assert_eq!(arg_tos(&dropped), vec![("log x2", 0), ("log x2", 2)]);
+
+        // entries of the same call are not duplicated
+        let repeated = record(&[], &[("log", 0), ("join", 1), ("log", 0)]);
+        let dropped = repeated.with_usages(&names(&[]), &names(&["log", "join"]), order);
+        assert_eq!(arg_tos(&dropped), vec![("log", 0), ("join", 1)]);
+    }
+}
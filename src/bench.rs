@@ -7,7 +7,7 @@ use std::time::Instant;
 pub fn benchmark_string_matchers() -> std::io::Result<()> {
     let mut strings: Vec<String> = Vec::new();
 
-    for i in 0..2048 {
+    for _ in 0..2048 {
         let s: String = rand::thread_rng()
             .sample_iter(&Alphanumeric)
             .take(16)
@@ -21,7 +21,7 @@ pub fn benchmark_string_matchers() -> std::io::Result<()> {
     }
     let t0 = Instant::now();
     for s in &strings {
-        if let Some(m) = RE.find(&s) {
+        if let Some(m) = RE.find(s) {
             std::hint::black_box(m);
         }
     }
@@ -40,11 +40,11 @@ pub fn benchmark_string_matchers() -> std::io::Result<()> {
     );
 
     lazy_static! {
-        static ref RE_SET: regex::RegexSet = regex::RegexSet::new(&["=", "\n"]).unwrap();
+        static ref RE_SET: regex::RegexSet = regex::RegexSet::new(["=", "\n"]).unwrap();
     }
     let t0 = Instant::now();
     for s in &strings {
-        let matches: Vec<_> = RE_SET.matches(&s).into_iter().collect();
+        let matches: Vec<_> = RE_SET.matches(s).into_iter().collect();
         if matches.is_empty() {
             std::hint::black_box(&s);
         }
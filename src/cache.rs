@@ -0,0 +1,376 @@
+use crate::slice_structs::Sample;
+use crate::stats::FilterReason;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Version of the cached results, bumped whenever import or vectorization produce different
+/// results for the same input and configuration
+const CACHE_VERSION: u32 = 5;
+
+/// Version of the tool and of the cached results, cache entries of any other version are discarded
+fn version() -> String {
+    format!("{}+cache.{}", env!("CARGO_PKG_VERSION"), CACHE_VERSION)
+}
+
+/// Everything `import_slices` produces for one slice input
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct InputResult {
+    pub num_files: usize,
+    pub num_scopes: usize,
+    pub num_objects: usize,
+    pub num_candidates: usize,
+    pub filter_counts: BTreeMap<FilterReason, usize>,
+    /// Archive members skipped per deny-list pattern
+    pub excluded_slices: BTreeMap<String, usize>,
+    pub samples: Vec<Sample>,
+}
+
+/// Identifies the state of a slice input on disk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputKey {
+    size: u64,
+    mtime_ns: u64,
+    hash: String,
+    /// Digest of state outside the input that its result depends on, such as project names
+    context: String,
+}
+
+/// Outcome of a cache lookup, a miss carries the key the fresh result has to be stored under
+pub enum Lookup {
+    Hit(InputResult),
+    Miss(InputKey),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Index {
+    version: String,
+    config: serde_json::Value,
+    entries: BTreeMap<String, InputKey>,
+}
+
+/// Per-input cache of import results. Inputs are keyed by path, size, mtime and content hash, the
+/// whole cache is invalidated if the tool or cache version or the cleaning configuration changes.
+pub struct ImportCache {
+    dir: PathBuf,
+    config: serde_json::Value,
+    entries: BTreeMap<String, InputKey>,
+    seen: HashSet<String>,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl ImportCache {
+    pub fn open(dir: &str, config: serde_json::Value) -> Self {
+        let dir = PathBuf::from(dir);
+        let version = version();
+
+        let index: Option<Index> = File::open(dir.join("index.json"))
+            .ok()
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).ok());
+
+        let entries = match index {
+            Some(index) if index.version == version && index.config == config => index.entries,
+            Some(_) => {
+                println!("[i] Tool version or configuration changed, discarding import cache");
+                let _ = fs::remove_dir_all(&dir);
+                BTreeMap::new()
+            }
+            None => BTreeMap::new(),
+        };
+
+        fs::create_dir_all(dir.join("results")).expect("Failed to create cache directory");
+
+        ImportCache {
+            dir,
+            config,
+            entries,
+            seen: HashSet::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn result_path(&self, name: &str, hash: &str) -> PathBuf {
+        self.dir.join("results").join(result_file(name, hash))
+    }
+
+    /// Returns the cached result if the input and its `context` are unchanged. Size and mtime are
+    /// checked first, the content is only hashed if they differ from the cached entry.
+    pub fn lookup(&mut self, path: &Path, context: &str) -> io::Result<Lookup> {
+        let name = path.display().to_string();
+        self.seen.insert(name.clone());
+
+        let meta = fs::metadata(path)?;
+        let size = meta.len();
+        let mtime_ns = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+
+        let cached = self
+            .entries
+            .get(&name)
+            .filter(|key| key.context == context)
+            .cloned();
+        let key = match cached {
+            Some(key) if key.size == size && key.mtime_ns == mtime_ns => key,
+            _ => {
+                let key = InputKey {
+                    size,
+                    mtime_ns,
+                    hash: hash_file(path)?,
+                    context: context.to_owned(),
+                };
+                match cached {
+                    Some(cached) if cached.hash == key.hash => key,
+                    _ => {
+                        self.misses += 1;
+                        return Ok(Lookup::Miss(key));
+                    }
+                }
+            }
+        };
+
+        match self.load(&name, &key.hash) {
+            Some(result) => {
+                self.hits += 1;
+                self.entries.insert(name, key);
+                Ok(Lookup::Hit(result))
+            }
+            None => {
+                self.misses += 1;
+                Ok(Lookup::Miss(key))
+            }
+        }
+    }
+
+    fn load(&self, name: &str, hash: &str) -> Option<InputResult> {
+        let file = File::open(self.result_path(name, hash)).ok()?;
+        let reader = zstd::Decoder::new(file).ok()?;
+        serde_json::from_reader(BufReader::new(reader)).ok()
+    }
+
+    pub fn store(&mut self, path: &Path, key: InputKey, result: &InputResult) {
+        let name = path.display().to_string();
+        let file =
+            File::create(self.result_path(&name, &key.hash)).expect("Failed to create cache entry");
+        let mut writer = zstd::Encoder::new(BufWriter::new(file), 0)
+            .expect("Failed to create cache entry")
+            .auto_finish();
+        serde_json::to_writer(&mut writer, result).expect("Failed to write cache entry");
+
+        self.entries.insert(name, key);
+    }
+
+    /// Forgets inputs that no longer exist, removes unreferenced results and writes the index
+    pub fn finish(mut self) {
+        let seen = &self.seen;
+        self.entries.retain(|name, _| seen.contains(name));
+
+        let referenced: HashSet<String> = self
+            .entries
+            .iter()
+            .map(|(name, key)| result_file(name, &key.hash))
+            .collect();
+        if let Ok(results) = fs::read_dir(self.dir.join("results")) {
+            for entry in results.flatten() {
+                if !referenced.contains(entry.file_name().to_string_lossy().as_ref()) {
+                    let _ = fs::remove_file(entry.path());
+                }
+            }
+        }
+
+        let index = Index {
+            version: version(),
+            config: self.config,
+            entries: self.entries,
+        };
+        let file = File::create(self.dir.join("index.json")).expect("Failed to open cache index");
+        serde_json::to_writer(BufWriter::new(file), &index).expect("Failed to write cache index");
+    }
+}
+
+/// Results hold the provenance of their input, so identical files at different paths get their
+/// own result file
+fn result_file(name: &str, hash: &str) -> String {
+    let path_hash = blake3::hash(name.as_bytes()).to_hex();
+    format!("{}-{}.json.zst", hash, &path_hash[..16])
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Counts recorded while importing a single input, given the counts before and after
+pub fn count_delta<K: Ord + Clone>(
+    before: &BTreeMap<K, usize>,
+    after: &BTreeMap<K, usize>,
+) -> BTreeMap<K, usize> {
+    after
+        .iter()
+        .map(|(key, n)| (key.clone(), n - before.get(key).unwrap_or(&0)))
+        .filter(|(_, n)| *n > 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("extys-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config(granularity: &str) -> serde_json::Value {
+        serde_json::json!({ "label_granularity": granularity })
+    }
+
+    fn set_mtime(path: &Path, secs: u64) {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    /// Stores a result with `num_files` for `input`, to recognize it when it is replayed
+    fn store(cache: &mut ImportCache, input: &Path, context: &str, num_files: usize) {
+        let key = match cache.lookup(input, context).unwrap() {
+            Lookup::Miss(key) => key,
+            Lookup::Hit(_) => panic!("Expected a cache miss"),
+        };
+        let result = InputResult {
+            num_files,
+            ..Default::default()
+        };
+        cache.store(input, key, &result);
+    }
+
+    fn replayed(cache: &mut ImportCache, input: &Path, context: &str) -> Option<usize> {
+        match cache.lookup(input, context).unwrap() {
+            Lookup::Hit(result) => Some(result.num_files),
+            Lookup::Miss(_) => None,
+        }
+    }
+
+    #[test]
+    fn unchanged_input_is_replayed() {
+        let dir = scratch_dir("cache-unchanged");
+        let input = dir.join("s.json");
+        fs::write(&input, "{}").unwrap();
+        let cache_dir = dir.join("cache");
+        let cache_dir = cache_dir.to_str().unwrap();
+
+        let mut cache = ImportCache::open(cache_dir, config("base"));
+        store(&mut cache, &input, "", 7);
+        cache.finish();
+
+        let mut cache = ImportCache::open(cache_dir, config("base"));
+        assert_eq!(replayed(&mut cache, &input, ""), Some(7));
+        assert_eq!((cache.hits, cache.misses), (1, 0));
+    }
+
+    #[test]
+    fn changed_input_is_imported_again() {
+        let dir = scratch_dir("cache-changed");
+        let input = dir.join("s.json");
+        fs::write(&input, "{\"a\": 1}").unwrap();
+        set_mtime(&input, 1_000);
+        let cache_dir = dir.join("cache");
+        let mut cache = ImportCache::open(cache_dir.to_str().unwrap(), config("base"));
+        store(&mut cache, &input, "", 1);
+
+        // touched but unchanged content is replayed
+        set_mtime(&input, 2_000);
+        assert_eq!(replayed(&mut cache, &input, ""), Some(1));
+
+        // same size, but different content
+        fs::write(&input, "{\"b\": 2}").unwrap();
+        set_mtime(&input, 3_000);
+        assert_eq!(replayed(&mut cache, &input, ""), None);
+        store(&mut cache, &input, "", 2);
+        assert_eq!(replayed(&mut cache, &input, ""), Some(2));
+
+        // different size
+        fs::write(&input, "{\"b\": 22}").unwrap();
+        assert_eq!(replayed(&mut cache, &input, ""), None);
+    }
+
+    #[test]
+    fn changed_configuration_invalidates_cache() {
+        let dir = scratch_dir("cache-config");
+        let input = dir.join("s.json");
+        fs::write(&input, "{}").unwrap();
+        let cache_dir = dir.join("cache");
+        let cache_dir = cache_dir.to_str().unwrap();
+
+        let mut cache = ImportCache::open(cache_dir, config("base"));
+        store(&mut cache, &input, "", 1);
+        cache.finish();
+
+        let mut cache = ImportCache::open(cache_dir, config("full"));
+        assert_eq!(replayed(&mut cache, &input, ""), None);
+    }
+
+    #[test]
+    fn changed_version_invalidates_cache() {
+        let dir = scratch_dir("cache-version");
+        let input = dir.join("s.json");
+        fs::write(&input, "{}").unwrap();
+        let cache_dir = dir.join("cache");
+
+        let mut cache = ImportCache::open(cache_dir.to_str().unwrap(), config("base"));
+        store(&mut cache, &input, "", 1);
+        cache.finish();
+
+        let index_path = cache_dir.join("index.json");
+        let mut index: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&index_path).unwrap()).unwrap();
+        assert_eq!(index["version"], version());
+        index["version"] = format!("{}+cache.0", env!("CARGO_PKG_VERSION")).into();
+        fs::write(&index_path, index.to_string()).unwrap();
+
+        let mut cache = ImportCache::open(cache_dir.to_str().unwrap(), config("base"));
+        assert_eq!(replayed(&mut cache, &input, ""), None);
+    }
+
+    #[test]
+    fn changed_context_invalidates_entry() {
+        let dir = scratch_dir("cache-context");
+        let input = dir.join("s.json");
+        fs::write(&input, "{}").unwrap();
+        let cache_dir = dir.join("cache");
+
+        let mut cache = ImportCache::open(cache_dir.to_str().unwrap(), config("base"));
+        store(&mut cache, &input, "names-a", 1);
+        assert_eq!(replayed(&mut cache, &input, "names-a"), Some(1));
+        assert_eq!(replayed(&mut cache, &input, "names-b"), None);
+    }
+
+    #[test]
+    fn identical_inputs_keep_their_own_results() {
+        let dir = scratch_dir("cache-identical");
+        let inputs = [dir.join("a.json"), dir.join("b.json")];
+        let cache_dir = dir.join("cache");
+        let mut cache = ImportCache::open(cache_dir.to_str().unwrap(), config("base"));
+        for (i, input) in inputs.iter().enumerate() {
+            fs::write(input, "{}").unwrap();
+            store(&mut cache, input, "", i);
+        }
+        cache.finish();
+
+        let mut cache = ImportCache::open(cache_dir.to_str().unwrap(), config("base"));
+        assert_eq!(replayed(&mut cache, &inputs[0], ""), Some(0));
+        assert_eq!(replayed(&mut cache, &inputs[1], ""), Some(1));
+        assert_eq!(fs::read_dir(cache_dir.join("results")).unwrap().count(), 2);
+    }
+}
@@ -0,0 +1,420 @@
+use crate::neardup::{self, MinHashIndex};
+use crate::slice_structs::{FeatureRecord, FeatureValues, Sample};
+use crate::utils;
+use glob::Pattern;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader};
+
+/// Matches removed per held-out source
+#[derive(Debug, Default, Serialize)]
+pub struct SourceCounts {
+    pub num_features: usize,
+    pub exact: usize,
+    pub near: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DecontaminationStats {
+    pub sources: BTreeMap<String, SourceCounts>,
+    /// Number of slice files skipped per deny-list pattern
+    pub excluded_slices: BTreeMap<String, usize>,
+}
+
+/// Sample of a held-out dataset
+enum HeldOut {
+    /// Feature string, near matches read its usages from the default layout
+    Feature(String),
+    /// Fields of a feature record, matched exactly on variable, scope and usages
+    Record(FeatureValues),
+}
+
+/// Exact key of the variable, scope and usages of a feature, independent of its layout. Values
+/// are cleaned like the rendered feature.
+fn values_key(values: &FeatureValues, language: Option<&str>) -> u64 {
+    let clean = |s: &String| utils::clean_feature(s, language);
+    let mut hasher = DefaultHasher::new();
+    clean(&values.variable).hash(&mut hasher);
+    clean(&values.scope).hash(&mut hasher);
+    values
+        .calls
+        .iter()
+        .map(clean)
+        .collect::<Vec<_>>()
+        .hash(&mut hasher);
+    values
+        .arg_tos
+        .iter()
+        .map(clean)
+        .collect::<Vec<_>>()
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+impl HeldOut {
+    fn parse(value: serde_json::Value) -> Option<Self> {
+        match value {
+            serde_json::Value::String(s) => Some(HeldOut::Feature(s)),
+            serde_json::Value::Object(mut o) => {
+                match o.remove("text").or_else(|| o.remove("feature")) {
+                    Some(serde_json::Value::String(s)) => Some(HeldOut::Feature(s)),
+                    Some(_) => None,
+                    None => {
+                        let record: FeatureRecord =
+                            serde_json::from_value(serde_json::Value::Object(o)).ok()?;
+                        // records list a call once per position the variable is passed at
+                        let mut arg_tos: Vec<String> =
+                            record.arg_tos.into_iter().map(|a| a.call).collect();
+                        arg_tos.dedup();
+                        Some(HeldOut::Record(FeatureValues {
+                            variable: record.variable,
+                            scope: record.scope.join("::"),
+                            calls: record.calls,
+                            arg_tos,
+                            ..Default::default()
+                        }))
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn tokens(&self, language: Option<&str>) -> Vec<u64> {
+        match self {
+            HeldOut::Feature(feature) => neardup::feature_tokens(feature),
+            HeldOut::Record(values) => neardup::value_tokens(values, language),
+        }
+    }
+}
+
+/// Reads the samples of a held-out dataset: a JSON array of features such as `feature_vec.json`,
+/// JSONL with a `text` or `feature` field, or feature records such as `feature_records.jsonl`
+fn load_held_out(path: &str) -> Vec<HeldOut> {
+    let file = File::open(path).expect("Failed to open decontamination source");
+
+    if path.ends_with(".jsonl") {
+        BufReader::new(file)
+            .lines()
+            .map(|l| l.expect("Failed to read decontamination source"))
+            .filter(|l| !l.trim().is_empty())
+            .filter_map(|l| {
+                HeldOut::parse(
+                    serde_json::from_str(&l).expect("Failed to parse decontamination source"),
+                )
+            })
+            .collect()
+    } else {
+        let values: Vec<serde_json::Value> = serde_json::from_reader(BufReader::new(file))
+            .expect("Failed to parse decontamination source");
+        values.into_iter().filter_map(HeldOut::parse).collect()
+    }
+}
+
+/// Removes samples that exactly or nearly match a sample of a held-out dataset
+pub struct Decontaminator {
+    sources: Vec<String>,
+    language: Option<String>,
+    /// Source of each held-out feature string
+    exact: HashMap<String, usize>,
+    /// Source of each held-out record, by `values_key`
+    exact_values: HashMap<u64, usize>,
+    /// Near matches compare variable, scope and usages of the features
+    index: Option<MinHashIndex>,
+    entry_sources: Vec<usize>,
+    counts: Vec<SourceCounts>,
+}
+
+impl Decontaminator {
+    pub fn load(paths: &[String], threshold: Option<f64>, language: Option<String>) -> Self {
+        let mut exact = HashMap::new();
+        let mut exact_values = HashMap::new();
+        let mut index = threshold.map(MinHashIndex::new);
+        let mut entry_sources = Vec::new();
+        let mut counts = Vec::new();
+
+        for (i, path) in paths.iter().enumerate() {
+            let held_out = load_held_out(path);
+            println!(
+                "[i] Loaded {} held-out features from '{}'",
+                held_out.len(),
+                path
+            );
+            counts.push(SourceCounts {
+                num_features: held_out.len(),
+                ..Default::default()
+            });
+
+            for sample in held_out {
+                if let Some(index) = index.as_mut() {
+                    let tokens = sample.tokens(language.as_deref());
+                    if !tokens.is_empty() {
+                        let keys = index.band_keys(&tokens, "");
+                        index.insert(tokens, keys);
+                        entry_sources.push(i);
+                    }
+                }
+                match sample {
+                    HeldOut::Feature(feature) => {
+                        exact.entry(feature).or_insert(i);
+                    }
+                    HeldOut::Record(values) => {
+                        exact_values
+                            .entry(values_key(&values, language.as_deref()))
+                            .or_insert(i);
+                    }
+                }
+            }
+        }
+
+        Decontaminator {
+            sources: paths.to_vec(),
+            language,
+            exact,
+            exact_values,
+            index,
+            entry_sources,
+            counts,
+        }
+    }
+
+    /// Returns whether the sample matches a held-out sample and counts the match for its source.
+    /// Near matches are found on the values of the sample, whatever the layout of its feature.
+    pub fn check(&mut self, sample: &Sample) -> bool {
+        let language = self.language.as_deref();
+        let exact = self
+            .exact
+            .get(&sample.feature)
+            .or_else(|| self.exact_values.get(&values_key(&sample.values, language)));
+        if let Some(source) = exact {
+            self.counts[*source].exact += 1;
+            return true;
+        }
+
+        if let Some(index) = self.index.as_ref() {
+            let tokens = neardup::value_tokens(&sample.values, language);
+            if !tokens.is_empty() {
+                let keys = index.band_keys(&tokens, "");
+                if let Some(entry) = index.find(&tokens, &keys) {
+                    self.counts[self.entry_sources[entry as usize]].near += 1;
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    pub fn stats(self, excluded_slices: BTreeMap<String, usize>) -> DecontaminationStats {
+        DecontaminationStats {
+            sources: self.sources.into_iter().zip(self.counts).collect(),
+            excluded_slices,
+        }
+    }
+}
+
+/// Deny-list of slice paths, matched against the slice file path and archive members
+pub struct SliceDenyList {
+    patterns: Vec<Pattern>,
+    pub counts: BTreeMap<String, usize>,
+}
+
+impl SliceDenyList {
+    pub fn new(patterns: &[String]) -> Self {
+        SliceDenyList {
+            patterns: patterns
+                .iter()
+                .map(|p| Pattern::new(p).expect("Failed to parse slice deny-list pattern"))
+                .collect(),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    fn find(&self, source: &str) -> Option<&Pattern> {
+        let paths = match source.split_once("!/") {
+            Some((archive, member)) => vec![archive, member],
+            None => vec![source],
+        };
+
+        self.patterns
+            .iter()
+            .find(|p| paths.iter().any(|path| p.matches(path)))
+    }
+
+    /// Returns whether the slice source matches a pattern, without counting the match
+    pub fn matches(&self, source: &str) -> bool {
+        self.find(source).is_some()
+    }
+
+    /// Returns whether the slice source matches a pattern and counts the match
+    pub fn check(&mut self, source: &str) -> bool {
+        match self.find(source) {
+            Some(pattern) => {
+                *self.counts.entry(pattern.as_str().to_string()).or_insert(0) += 1;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn sample(variable: &str, scope: &str, calls: &[&str]) -> Sample {
+        let calls: Vec<String> = calls.iter().map(|c| c.to_string()).collect();
+        Sample {
+            feature: format!(
+                "Variable: {} ; Scope: {} ; Calls: {} ;",
+                variable,
+                scope,
+                calls.join(", ")
+            ),
+            values: FeatureValues {
+                variable: variable.to_string(),
+                scope: scope.to_string(),
+                calls,
+                ..Default::default()
+            },
+            label: "string".to_string(),
+            num_usages: 1,
+            project: "projA".to_string(),
+            source: "projA/slices.json".to_string(),
+            scope: scope.to_string(),
+            variable: variable.to_string(),
+            split_strategy: None,
+            chunk: 0,
+            augmentation: None,
+            literal: false,
+            record: None,
+        }
+    }
+
+    /// Writes a held-out source, the extension selects JSON or JSONL
+    fn held_out(extension: &str, content: &str) -> String {
+        static NUM_FILES: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "extys-held-out-{}-{}.{}",
+            std::process::id(),
+            NUM_FILES.fetch_add(1, Ordering::Relaxed),
+            extension
+        ));
+        std::fs::write(&path, content).unwrap();
+        path.display().to_string()
+    }
+
+    fn record(variable: &str, scope: &[&str], calls: &[&str]) -> String {
+        serde_json::json!({
+            "variable": variable,
+            "scope": scope,
+            "calls": calls,
+            "arg_tos": [],
+            "defined_by": null,
+            "literal": false,
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn features_are_matched_exactly() {
+        let target = sample("user", "app.ts::load", &["fetch", "then"]);
+        let source = held_out("json", &serde_json::json!([target.feature]).to_string());
+        let mut decontaminator = Decontaminator::load(&[source], None, None);
+
+        assert!(decontaminator.check(&target));
+        assert!(!decontaminator.check(&sample("user", "app.ts::load", &["fetch"])));
+    }
+
+    #[test]
+    fn records_are_matched_without_threshold() {
+        let source = held_out(
+            "jsonl",
+            &record("user", &["app.ts", "load"], &["fetch", "then"]),
+        );
+        let mut decontaminator = Decontaminator::load(&[source], None, None);
+
+        // matched on the values, whatever the layout of the feature
+        let mut target = sample("user", "app.ts::load", &["fetch", "then"]);
+        target.feature = "user | fetch/then".to_string();
+        assert!(decontaminator.check(&target));
+        assert!(!decontaminator.check(&sample("user", "app.ts::load", &["then", "fetch"])));
+    }
+
+    #[test]
+    fn records_are_matched_nearly() {
+        let calls = ["a", "b", "c", "d", "e", "f", "g", "h", "i"];
+        let source = held_out("jsonl", &record("user", &["app.ts", "load"], &calls));
+        let mut decontaminator = Decontaminator::load(&[source], Some(0.7), None);
+
+        let mut similar = calls.to_vec();
+        similar[8] = "j";
+        assert!(decontaminator.check(&sample("user", "app.ts::load", &similar)));
+        assert!(!decontaminator.check(&sample("other", "main.ts::run", &["x", "y"])));
+    }
+
+    #[test]
+    fn matches_are_counted_per_source() {
+        let first = sample("user", "app.ts::load", &["fetch"]);
+        let second = sample("name", "app.ts::load", &["trim"]);
+        let sources = [
+            held_out("json", &serde_json::json!([first.feature]).to_string()),
+            held_out(
+                "jsonl",
+                &format!(
+                    "{}\n{}\n",
+                    serde_json::json!({ "text": second.feature }),
+                    record("id", &["app.ts", "load"], &["parse"])
+                ),
+            ),
+        ];
+        let mut decontaminator = Decontaminator::load(&sources, None, None);
+        for target in [
+            &first,
+            &second,
+            &second,
+            &sample("id", "app.ts::load", &["parse"]),
+        ] {
+            assert!(decontaminator.check(target));
+        }
+        assert!(!decontaminator.check(&sample("x", "app.ts::load", &["fetch"])));
+
+        let stats = decontaminator.stats(BTreeMap::new());
+        let counts: Vec<(usize, usize, usize)> = sources
+            .iter()
+            .map(|s| {
+                let c = &stats.sources[s];
+                (c.num_features, c.exact, c.near)
+            })
+            .collect();
+        assert_eq!(counts, vec![(1, 1, 0), (2, 3, 0)]);
+    }
+
+    #[test]
+    fn deny_list_matches_paths_and_archive_members() {
+        let mut deny_list =
+            SliceDenyList::new(&["**/vendor/**".to_string(), "*/test_*.json".to_string()]);
+
+        assert!(deny_list.check("slices/projA/vendor/lib/slices.json"));
+        assert!(deny_list.check("slices/projB.tar.gz!/src/test_util.json"));
+        assert!(deny_list.check("slices/projB.tar.gz!/vendor/x/a.json"));
+        assert!(!deny_list.check("slices/projA/src/slices.json"));
+        assert!(!deny_list.check("slices/projB.tar.gz!/src/util.json"));
+        assert!(deny_list.matches("slices/projC/vendor/a.json"));
+
+        let counts: Vec<(&str, usize)> = deny_list
+            .counts
+            .iter()
+            .map(|(p, n)| (p.as_str(), *n))
+            .collect();
+        assert_eq!(counts, vec![("**/vendor/**", 2), ("*/test_*.json", 1)]);
+    }
+}
@@ -0,0 +1,706 @@
+use crate::augment::AugmentMethod;
+use crate::slice_structs::{FeatureRecord, Sample};
+use crate::stats::RunReport;
+use crate::utils;
+use arrow_array::builder::{StringBuilder, UInt32Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use clap::ValueEnum;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, GzipLevel, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Formats the final dataset can be written in
+#[derive(clap::ValueEnum, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// `feature_vec.json` and `class_label_vec.json` arrays
+    Json,
+    /// Columnar `.parquet` files
+    Parquet,
+    /// Hugging Face `datasets` directory with JSONL splits, label mapping and dataset card
+    Hf,
+    /// `feature_records.jsonl` with structured features instead of feature strings
+    Records,
+}
+
+/// Compression codec of the written Parquet files
+#[derive(clap::ValueEnum, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParquetCompression {
+    None,
+    #[default]
+    Snappy,
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+impl ParquetCompression {
+    fn codec(&self) -> Compression {
+        match self {
+            ParquetCompression::None => Compression::UNCOMPRESSED,
+            ParquetCompression::Snappy => Compression::SNAPPY,
+            ParquetCompression::Gzip => Compression::GZIP(GzipLevel::default()),
+            ParquetCompression::Zstd => Compression::ZSTD(ZstdLevel::default()),
+            ParquetCompression::Lz4 => Compression::LZ4_RAW,
+        }
+    }
+}
+
+const SPLIT_NAMES: [&str; 3] = ["train", "val", "test"];
+
+/// Deterministic assignment of samples to train/val/test splits
+pub struct Splits {
+    /// Upper bound of each split on the unit interval
+    bounds: Vec<f64>,
+}
+
+impl Splits {
+    /// Fractions are normalized, so `0.8,0.1,0.1` and `8,1,1` are equivalent
+    pub fn new(fractions: &[f64]) -> Result<Self, String> {
+        if fractions.is_empty() || fractions.len() > SPLIT_NAMES.len() {
+            return Err("Expected one to three split fractions (train, val, test)".to_owned());
+        }
+        if !fractions.iter().all(|f| f.is_finite() && *f >= 0.0)
+            || fractions.iter().sum::<f64>() <= 0.0
+        {
+            return Err("Split fractions must be non-negative and not all zero".to_owned());
+        }
+
+        let total: f64 = fractions.iter().sum();
+        let mut acc = 0.0;
+        let mut bounds: Vec<f64> = fractions
+            .iter()
+            .map(|f| {
+                acc += f / total;
+                acc
+            })
+            .collect();
+        *bounds.last_mut().unwrap() = 1.0;
+
+        Ok(Splits { bounds })
+    }
+
+    pub fn names(&self) -> &'static [&'static str] {
+        &SPLIT_NAMES[..self.bounds.len()]
+    }
+
+    /// Samples are assigned by a hash of their project, scope and variable, so all chunks of one
+    /// object end up in the same split and the assignment is stable across runs
+    pub fn assign(&self, sample: &Sample) -> usize {
+        let x = utils::stable_fraction(&[&sample.project, &sample.scope, &sample.variable]);
+
+        self.bounds
+            .iter()
+            .position(|b| x < *b)
+            .unwrap_or(self.bounds.len() - 1)
+    }
+}
+
+const BATCH_SIZE: usize = 8192;
+
+/// Columns buffered for the next record batch
+struct RowBuffer {
+    feature: StringBuilder,
+    label: StringBuilder,
+    label_id: UInt32Builder,
+    num_usages: UInt32Builder,
+    language: StringBuilder,
+    project: StringBuilder,
+    source: StringBuilder,
+    scope: StringBuilder,
+    variable: StringBuilder,
+    split_strategy: StringBuilder,
+    chunk: UInt32Builder,
+    augmentation: StringBuilder,
+    len: usize,
+}
+
+impl RowBuffer {
+    fn new() -> Self {
+        RowBuffer {
+            feature: StringBuilder::new(),
+            label: StringBuilder::new(),
+            label_id: UInt32Builder::new(),
+            num_usages: UInt32Builder::new(),
+            language: StringBuilder::new(),
+            project: StringBuilder::new(),
+            source: StringBuilder::new(),
+            scope: StringBuilder::new(),
+            variable: StringBuilder::new(),
+            split_strategy: StringBuilder::new(),
+            chunk: UInt32Builder::new(),
+            augmentation: StringBuilder::new(),
+            len: 0,
+        }
+    }
+
+    fn finish(&mut self, schema: &SchemaRef) -> RecordBatch {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.feature.finish()),
+            Arc::new(self.label.finish()),
+            Arc::new(self.label_id.finish()),
+            Arc::new(self.num_usages.finish()),
+            Arc::new(self.language.finish()),
+            Arc::new(self.project.finish()),
+            Arc::new(self.source.finish()),
+            Arc::new(self.scope.finish()),
+            Arc::new(self.variable.finish()),
+            Arc::new(self.split_strategy.finish()),
+            Arc::new(self.chunk.finish()),
+            Arc::new(self.augmentation.finish()),
+        ];
+        self.len = 0;
+        RecordBatch::try_new(schema.clone(), columns).expect("Failed to build record batch")
+    }
+}
+
+/// Streams samples into Parquet files. Without splits a single `dataset.parquet` is written,
+/// otherwise one file per split in a `dataset/split=<name>/` layout.
+pub struct ParquetWriter {
+    schema: SchemaRef,
+    language: &'static str,
+    writers: Vec<ArrowWriter<File>>,
+    buffers: Vec<RowBuffer>,
+    pub num_samples: usize,
+}
+
+impl ParquetWriter {
+    pub fn create(
+        output_dir: &str,
+        language: &'static str,
+        splits: Option<&Splits>,
+        compression: ParquetCompression,
+    ) -> Self {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("feature", DataType::Utf8, false),
+            Field::new("label", DataType::Utf8, false),
+            Field::new("label_id", DataType::UInt32, false),
+            Field::new("num_usages", DataType::UInt32, false),
+            Field::new("language", DataType::Utf8, false),
+            Field::new("project", DataType::Utf8, false),
+            Field::new("source", DataType::Utf8, false),
+            Field::new("scope", DataType::Utf8, false),
+            Field::new("variable", DataType::Utf8, false),
+            Field::new("split_strategy", DataType::Utf8, true),
+            Field::new("chunk", DataType::UInt32, false),
+            Field::new("augmentation", DataType::Utf8, true),
+        ]));
+        let props = WriterProperties::builder()
+            .set_compression(compression.codec())
+            .build();
+
+        let paths = match splits {
+            Some(splits) => splits
+                .names()
+                .iter()
+                .map(|name| {
+                    let dir = Path::new(output_dir)
+                        .join("dataset")
+                        .join(format!("split={}", name));
+                    fs::create_dir_all(&dir).expect("Failed to create Parquet split directory");
+                    dir.join("part-00000.parquet")
+                })
+                .collect(),
+            None => vec![Path::new(output_dir).join("dataset.parquet")],
+        };
+
+        let writers = paths
+            .iter()
+            .map(|path| {
+                let file = File::create(path).expect("Failed to open Parquet file");
+                ArrowWriter::try_new(file, schema.clone(), Some(props.clone()))
+                    .expect("Failed to create Parquet writer")
+            })
+            .collect();
+
+        ParquetWriter {
+            buffers: paths.iter().map(|_| RowBuffer::new()).collect(),
+            schema,
+            language,
+            writers,
+            num_samples: 0,
+        }
+    }
+
+    pub fn write(&mut self, sample: &Sample, label_id: u32, split: usize) {
+        let buffer = &mut self.buffers[split];
+        buffer.feature.append_value(&sample.feature);
+        buffer.label.append_value(&sample.label);
+        buffer.label_id.append_value(label_id);
+        buffer.num_usages.append_value(sample.num_usages as u32);
+        buffer.language.append_value(self.language);
+        buffer.project.append_value(&sample.project);
+        buffer.source.append_value(&sample.source);
+        buffer.scope.append_value(&sample.scope);
+        buffer.variable.append_value(&sample.variable);
+        buffer.split_strategy.append_option(
+            sample
+                .split_strategy
+                .and_then(|s| s.to_possible_value())
+                .map(|v| v.get_name().to_owned()),
+        );
+        buffer.chunk.append_value(sample.chunk as u32);
+        buffer.augmentation.append_option(
+            sample
+                .augmentation
+                .and_then(|a| a.to_possible_value())
+                .map(|v| v.get_name().to_owned()),
+        );
+        buffer.len += 1;
+        self.num_samples += 1;
+
+        if buffer.len >= BATCH_SIZE {
+            let batch = buffer.finish(&self.schema);
+            self.writers[split]
+                .write(&batch)
+                .expect("Failed to write Parquet batch");
+        }
+    }
+
+    pub fn finish(mut self) {
+        for (buffer, mut writer) in self.buffers.iter_mut().zip(self.writers) {
+            if buffer.len > 0 {
+                writer
+                    .write(&buffer.finish(&self.schema))
+                    .expect("Failed to write Parquet batch");
+            }
+            writer.close().expect("Failed to close Parquet file");
+        }
+    }
+}
+
+/// Writes a directory loadable with Hugging Face `datasets`: one JSONL file per split with `text`
+/// and integer `label` fields, `label_mapping.json` and a `README.md` dataset card
+pub struct HfWriter {
+    dir: PathBuf,
+    split_names: Vec<&'static str>,
+    files: Vec<BufWriter<File>>,
+    /// Number of samples per split file
+    counts: Vec<usize>,
+    pub num_samples: usize,
+}
+
+/// Split that holds the augmented samples, kept apart from the training split they derive from
+const AUGMENTED_SPLIT: &str = "train_augmented";
+
+#[derive(Serialize)]
+struct HfRecord<'a> {
+    text: &'a str,
+    label: u32,
+}
+
+/// Split names as used by Hugging Face
+fn hf_split_name(name: &str) -> &str {
+    match name {
+        "val" => "validation",
+        name => name,
+    }
+}
+
+impl HfWriter {
+    /// With `augmented`, augmented samples are written to their own `train_augmented` split
+    pub fn create(output_dir: &str, splits: Option<&Splits>, augmented: bool) -> Self {
+        let dir = Path::new(output_dir).join("hf_dataset");
+        fs::create_dir_all(&dir).expect("Failed to create dataset directory");
+
+        let mut split_names = splits.map_or(vec!["train"], |s| s.names().to_vec());
+        if augmented {
+            split_names.push(AUGMENTED_SPLIT);
+        }
+        let files = split_names
+            .iter()
+            .map(|name| {
+                let path = dir.join(format!("{}.jsonl", hf_split_name(name)));
+                BufWriter::new(File::create(path).expect("Failed to open split file"))
+            })
+            .collect();
+
+        HfWriter {
+            dir,
+            counts: vec![0; split_names.len()],
+            split_names,
+            files,
+            num_samples: 0,
+        }
+    }
+
+    pub fn write(&mut self, sample: &Sample, label_id: u32, split: usize) {
+        let split = match sample.augmentation {
+            Some(_) => {
+                assert_eq!(
+                    self.split_names.last(),
+                    Some(&AUGMENTED_SPLIT),
+                    "Failed to find split for augmented samples"
+                );
+                self.files.len() - 1
+            }
+            None => split,
+        };
+        self.counts[split] += 1;
+        let file = &mut self.files[split];
+        let record = HfRecord {
+            text: &sample.feature,
+            label: label_id,
+        };
+        serde_json::to_writer(&mut *file, &record).expect("Failed to write sample");
+        file.write_all(b"\n").expect("Failed to write sample");
+        self.num_samples += 1;
+    }
+
+    /// Flushes the splits and writes label mapping and dataset card. `labels` is the label
+    /// vocabulary indexed by id, `report` has to describe the finished dataset
+    pub fn finish(self, labels: &[String], report: &RunReport) {
+        for mut file in self.files {
+            file.flush().expect("Failed to flush split file");
+        }
+
+        let id2label: BTreeMap<u32, &str> = labels
+            .iter()
+            .enumerate()
+            .map(|(id, label)| (id as u32, label.as_str()))
+            .collect();
+        let label2id: BTreeMap<&str, u32> = labels
+            .iter()
+            .enumerate()
+            .map(|(id, label)| (label.as_str(), id as u32))
+            .collect();
+
+        let file = File::create(self.dir.join("label_mapping.json"))
+            .expect("Failed to open label mapping file");
+        serde_json::to_writer_pretty(
+            file,
+            &serde_json::json!({ "label2id": label2id, "id2label": id2label }),
+        )
+        .expect("Failed to write label mapping file");
+
+        fs::write(
+            self.dir.join("README.md"),
+            dataset_card(&self.split_names, &self.counts, labels, report),
+        )
+        .expect("Failed to write dataset card");
+    }
+}
+
+#[derive(Serialize)]
+struct LabeledRecord<'a> {
+    #[serde(flatten)]
+    record: &'a FeatureRecord,
+    language: &'static str,
+    label: &'a str,
+    label_id: u32,
+    split: Option<&'static str>,
+    augmentation: Option<AugmentMethod>,
+}
+
+/// Streams the structured features of the samples into `feature_records.jsonl`
+pub struct RecordWriter {
+    file: BufWriter<File>,
+    language: &'static str,
+    split_names: Option<&'static [&'static str]>,
+    pub num_samples: usize,
+}
+
+impl RecordWriter {
+    pub fn create(output_dir: &str, language: &'static str, splits: Option<&Splits>) -> Self {
+        let file = File::create(Path::new(output_dir).join("feature_records.jsonl"))
+            .expect("Failed to open feature record file");
+
+        RecordWriter {
+            file: BufWriter::new(file),
+            language,
+            split_names: splits.map(|s| s.names()),
+            num_samples: 0,
+        }
+    }
+
+    pub fn write(&mut self, sample: &Sample, label_id: u32, split: usize) {
+        let record = LabeledRecord {
+            record: sample
+                .record
+                .as_ref()
+                .expect("Failed to find structured features of sample"),
+            language: self.language,
+            label: &sample.label,
+            label_id,
+            split: self.split_names.map(|names| names[split]),
+            augmentation: sample.augmentation,
+        };
+        serde_json::to_writer(&mut self.file, &record).expect("Failed to write feature record");
+        self.file
+            .write_all(b"\n")
+            .expect("Failed to write feature record");
+        self.num_samples += 1;
+    }
+
+    pub fn finish(mut self) {
+        self.file
+            .flush()
+            .expect("Failed to flush feature record file");
+    }
+}
+
+/// Dataset card with `datasets` metadata, the generation parameters and the class distribution
+fn dataset_card(
+    split_names: &[&'static str],
+    counts: &[usize],
+    labels: &[String],
+    report: &RunReport,
+) -> String {
+    let yaml_str = |s: &str| serde_json::to_string(s).unwrap();
+    let md_cell = |s: &str| s.replace('|', "\\|");
+
+    let mut card = String::from("---\ntask_categories:\n- text-classification\nconfigs:\n- config_name: default\n  data_files:\n");
+    for name in split_names {
+        let _ = writeln!(
+            card,
+            "  - split: {0}\n    path: {0}.jsonl",
+            hf_split_name(name)
+        );
+    }
+    card.push_str("dataset_info:\n  features:\n  - name: text\n    dtype: string\n  - name: label\n    dtype:\n      class_label:\n        names:\n");
+    for label in labels {
+        let _ = writeln!(card, "        - {}", yaml_str(label));
+    }
+    card.push_str("  splits:\n");
+    for (name, n) in split_names.iter().zip(counts) {
+        let _ = writeln!(
+            card,
+            "  - name: {}\n    num_examples: {}",
+            hf_split_name(name),
+            n
+        );
+    }
+    card.push_str("---\n\n");
+
+    let _ = writeln!(
+        card,
+        "# exTyS type inference dataset\n\nGenerated by exTyS {} from Joern usage slices. Each `text` describes the usages of one variable, `label` is the id of its type, see `label_mapping.json`.\n",
+        report.version
+    );
+
+    card.push_str("## Splits\n\n| split | samples |\n|---|---:|\n");
+    for (name, n) in split_names.iter().zip(counts) {
+        let _ = writeln!(card, "| {} | {} |", hf_split_name(name), n);
+    }
+    if split_names.contains(&AUGMENTED_SPLIT) {
+        let _ = writeln!(
+            card,
+            "\n`{}` holds the augmented samples derived from `train`, they are not part of `train`.",
+            AUGMENTED_SPLIT
+        );
+    }
+
+    card.push_str("\n## Generation parameters\n\n| parameter | value |\n|---|---|\n");
+    if let Some(config) = report.config.as_object() {
+        for (key, value) in config {
+            let _ = writeln!(card, "| {} | `{}` |", key, md_cell(&value.to_string()));
+        }
+    }
+
+    let reserved = labels
+        .iter()
+        .filter(|l| !report.class_histogram.contains_key(l.as_str()))
+        .count();
+    let _ = writeln!(
+        card,
+        "\n## Class distribution\n\n{} samples in {} classes.\n",
+        report.dataset.num_samples, report.dataset.num_classes
+    );
+    if reserved > 0 {
+        let _ = writeln!(
+            card,
+            "Label ids are the ids of the label vocabulary (`label_vocab.json`). `names` lists the whole vocabulary in id order, {} of its labels have no samples in this dataset and their ids stay reserved.\n",
+            reserved
+        );
+    }
+    card.push_str("| id | label | samples |\n|---:|---|---:|\n");
+    for (id, label) in labels.iter().enumerate() {
+        let n = report.class_histogram.get(label).unwrap_or(&0);
+        let _ = writeln!(card, "| {} | `{}` | {} |", id, md_cell(label), n);
+    }
+
+    card
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slice_structs::FeatureValues;
+
+    fn sample(project: &str, scope: &str, variable: &str, chunk: usize) -> Sample {
+        Sample {
+            feature: format!("Variable: {} ; Scope: {} ;", variable, scope),
+            values: FeatureValues {
+                variable: variable.to_string(),
+                scope: scope.to_string(),
+                ..Default::default()
+            },
+            label: "string".to_string(),
+            num_usages: 1,
+            project: project.to_string(),
+            source: format!("{}/slices.json", project),
+            scope: scope.to_string(),
+            variable: variable.to_string(),
+            split_strategy: None,
+            chunk,
+            augmentation: None,
+            literal: false,
+            record: None,
+        }
+    }
+
+    #[test]
+    fn card_names_are_indexed_by_vocabulary_id() {
+        let labels: Vec<String> = ["Foo", "Reserved", "string"]
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+        let mut report = RunReport::default();
+        report.class_histogram.insert("Foo".to_string(), 3);
+        report.class_histogram.insert("string".to_string(), 5);
+
+        let card = dataset_card(&["train"], &[8], &labels, &report);
+        assert!(card.contains(
+            "        names:\n        - \"Foo\"\n        - \"Reserved\"\n        - \"string\"\n"
+        ));
+        assert!(card.contains("| 1 | `Reserved` | 0 |\n| 2 | `string` | 5 |"));
+        assert!(card.contains("1 of its labels have no samples"));
+
+        report.class_histogram.insert("Reserved".to_string(), 1);
+        assert!(!dataset_card(&["train"], &[8], &labels, &report).contains("no samples"));
+    }
+
+    #[test]
+    fn card_lists_augmented_split_separately() {
+        let labels = vec!["string".to_string()];
+        let mut report = RunReport::default();
+        report.class_histogram.insert("string".to_string(), 10);
+
+        let card = dataset_card(&["train", AUGMENTED_SPLIT], &[8, 2], &labels, &report);
+        assert!(card.contains("  - split: train_augmented\n    path: train_augmented.jsonl\n"));
+        assert!(card.contains("| train | 8 |\n| train_augmented | 2 |\n"));
+        assert!(card.contains("they are not part of `train`"));
+    }
+
+    #[test]
+    fn invalid_fractions_are_rejected() {
+        assert!(Splits::new(&[]).is_err());
+        assert!(Splits::new(&[0.7, 0.1, 0.1, 0.1]).is_err());
+        assert!(Splits::new(&[0.9, -0.1, 0.2]).is_err());
+        assert!(Splits::new(&[0.0, 0.0]).is_err());
+        assert!(Splits::new(&[f64::NAN, 0.1]).is_err());
+        assert!(Splits::new(&[0.0, 1.0]).is_ok());
+    }
+
+    #[test]
+    fn fractions_are_normalized() {
+        assert_eq!(
+            Splits::new(&[8.0, 1.0, 1.0]).unwrap().bounds,
+            Splits::new(&[0.8, 0.1, 0.1]).unwrap().bounds
+        );
+        assert_eq!(Splits::new(&[1.0]).unwrap().names(), &["train"]);
+        assert_eq!(Splits::new(&[1.0, 1.0]).unwrap().bounds, vec![0.5, 1.0]);
+    }
+
+    #[test]
+    fn assignment_is_stable_per_object() {
+        let splits = Splits::new(&[0.8, 0.1, 0.1]).unwrap();
+        for i in 0..100 {
+            let variable = format!("v{}", i);
+            let split = splits.assign(&sample("projA", "main", &variable, 0));
+            assert_eq!(split, splits.assign(&sample("projA", "main", &variable, 3)));
+            assert_eq!(
+                split,
+                Splits::new(&[0.8, 0.1, 0.1])
+                    .unwrap()
+                    .assign(&sample("projA", "main", &variable, 0))
+            );
+        }
+    }
+
+    #[test]
+    fn assignment_respects_fractions() {
+        let splits = Splits::new(&[0.8, 0.1, 0.1]).unwrap();
+        let mut counts = [0usize; 3];
+        for i in 0..10_000 {
+            let project = format!("p{}", i % 50);
+            let variable = format!("v{}", i);
+            counts[splits.assign(&sample(&project, "main", &variable, 0))] += 1;
+        }
+        assert!((7_500..8_500).contains(&counts[0]), "{:?}", counts);
+        assert!((700..1_300).contains(&counts[1]), "{:?}", counts);
+        assert!((700..1_300).contains(&counts[2]), "{:?}", counts);
+
+        let splits = Splits::new(&[1.0, 0.0, 1.0]).unwrap();
+        assert!(
+            (0..1_000).all(|i| splits.assign(&sample("projA", "main", &format!("v{}", i), 0)) != 1)
+        );
+    }
+
+    #[test]
+    fn records_carry_label_split_and_augmentation() {
+        let dir = std::env::temp_dir().join(format!("extys-records-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let splits = Splits::new(&[0.8, 0.1, 0.1]).unwrap();
+
+        let mut evaluation = sample("projA", "app.ts::load", "user", 0);
+        evaluation.record = Some(FeatureRecord {
+            variable: "user".to_string(),
+            scope: vec!["app.ts".to_string(), "load".to_string()],
+            calls: vec!["fetch x2".to_string()],
+            arg_tos: vec![crate::slice_structs::ArgTo {
+                call: "log".to_string(),
+                position: 1,
+            }],
+            defined_by: None,
+            literal: false,
+            literal_kind: None,
+            siblings: vec![],
+            subtokens: None,
+        });
+        let mut augmented = evaluation.clone();
+        augmented.augmentation = Some(AugmentMethod::Shuffle);
+
+        let mut writer = RecordWriter::create(dir.to_str().unwrap(), "typescript", Some(&splits));
+        writer.write(&evaluation, 3, 2);
+        writer.write(&augmented, 3, 0);
+        assert_eq!(writer.num_samples, 2);
+        writer.finish();
+
+        let content = std::fs::read_to_string(dir.join("feature_records.jsonl")).unwrap();
+        let records: Vec<serde_json::Value> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            records[0],
+            serde_json::json!({
+                "variable": "user",
+                "scope": ["app.ts", "load"],
+                "calls": ["fetch x2"],
+                "arg_tos": [{"call": "log", "position": 1}],
+                "defined_by": null,
+                "literal": false,
+                "literal_kind": null,
+                "language": "typescript",
+                "label": "string",
+                "label_id": 3,
+                "split": "test",
+                "augmentation": null,
+            })
+        );
+        assert_eq!(records[1]["split"], "train");
+        assert_eq!(records[1]["augmentation"], "shuffle");
+    }
+}
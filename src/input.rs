@@ -0,0 +1,255 @@
+use flate2::read::GzDecoder;
+use glob::glob;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Slice input found in the slice directory: a plain or compressed slice file, or an archive of slice files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    Json,
+    JsonGz,
+    JsonZst,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl InputKind {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+
+        if name.ends_with(".json") {
+            Some(InputKind::Json)
+        } else if name.ends_with(".json.gz") {
+            Some(InputKind::JsonGz)
+        } else if name.ends_with(".json.zst") {
+            Some(InputKind::JsonZst)
+        } else if name.ends_with(".tar") {
+            Some(InputKind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(InputKind::TarGz)
+        } else if name.ends_with(".tar.zst") {
+            Some(InputKind::TarZst)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_archive(&self) -> bool {
+        matches!(self, InputKind::Tar | InputKind::TarGz | InputKind::TarZst)
+    }
+}
+
+/// Collects all slice inputs below `dir`, archives are not opened yet
+pub fn find_slice_inputs(dir: &str) -> Vec<(PathBuf, InputKind)> {
+    let mut inputs = Vec::with_capacity(400_000);
+
+    for entry in
+        glob(&format!("{}/**/*", dir)).expect("Failed to read provided slice path as glob pattern")
+    {
+        match entry {
+            Ok(path) => {
+                if let Some(kind) = InputKind::from_path(&path) {
+                    if path.is_file() {
+                        inputs.push((path, kind));
+                    }
+                }
+            }
+            Err(e) => println!("[!] Failed to read slice path: {}", e),
+        }
+    }
+
+    inputs
+}
+
+/// Project a slice file belongs to: the first directory below the slice directory, or the name of
+/// the archive if it is placed directly in the slice directory
+pub fn project_name(slices_dir: &str, source: &str) -> String {
+    let (outer, member) = match source.split_once("!/") {
+        Some((outer, member)) => (outer, Some(member)),
+        None => (source, None),
+    };
+
+    let rel = Path::new(outer)
+        .strip_prefix(slices_dir)
+        .unwrap_or_else(|_| Path::new(outer));
+    let mut components = rel.components();
+
+    match (components.next(), components.next(), member) {
+        (Some(dir), Some(_), _) => dir.as_os_str().to_string_lossy().into_owned(),
+        (Some(archive), None, Some(_)) => {
+            let name = archive.as_os_str().to_string_lossy();
+            [".tar.gz", ".tar.zst", ".tgz", ".tar"]
+                .iter()
+                .find_map(|ext| name.strip_suffix(ext))
+                .unwrap_or(&name)
+                .to_string()
+        }
+        _ => String::new(),
+    }
+}
+
+/// Project of the slice files of an input, the same for all members of an archive
+pub fn input_project(slices_dir: &str, path: &Path, kind: InputKind) -> String {
+    let source = path.display().to_string();
+    if kind.is_archive() {
+        project_name(slices_dir, &format!("{}!/", source))
+    } else {
+        project_name(slices_dir, &source)
+    }
+}
+
+/// Wraps `reader` into the decoder required by `kind`
+fn decoder<'a, R: Read + 'a>(reader: R, kind: InputKind) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(match kind {
+        InputKind::Json | InputKind::Tar => Box::new(reader),
+        InputKind::JsonGz | InputKind::TarGz => Box::new(GzDecoder::new(reader)),
+        InputKind::JsonZst | InputKind::TarZst => Box::new(zstd::Decoder::new(reader)?),
+    })
+}
+
+/// Calls `f` with the provenance and the decompressed content of every slice file in the input.
+/// The provenance of archive members is `<archive path>!/<member path>`. Members that cannot be
+/// read are passed to `f` as an error and the remaining members are still visited. If the archive
+/// itself cannot be read further, the position is given as `<archive path>!/#<entry index>`.
+pub fn for_each_slice_file<F>(path: &Path, kind: InputKind, mut f: F) -> io::Result<()>
+where
+    F: FnMut(&str, io::Result<&str>),
+{
+    let reader = decoder(BufReader::new(File::open(path)?), kind)?;
+
+    if !kind.is_archive() {
+        let mut c = String::new();
+        BufReader::new(reader).read_to_string(&mut c)?;
+        f(&path.display().to_string(), Ok(&c));
+        return Ok(());
+    }
+
+    let mut archive = tar::Archive::new(reader);
+    for (i, entry) in archive.entries()?.enumerate() {
+        let position = || format!("{}!/#{}", path.display(), i);
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                f(&position(), Err(e));
+                continue;
+            }
+        };
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let member = match entry.path() {
+            Ok(member) => member.into_owned(),
+            Err(e) => {
+                f(&position(), Err(e));
+                continue;
+            }
+        };
+        let member_kind = match InputKind::from_path(&member) {
+            Some(k) if !k.is_archive() => k,
+            _ => continue,
+        };
+
+        let source = format!("{}!/{}", path.display(), member.display());
+        let mut c = String::new();
+        match decoder(entry, member_kind).and_then(|mut r| r.read_to_string(&mut c)) {
+            Ok(_) => f(&source, Ok(&c)),
+            Err(e) => f(&source, Err(e)),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    #[test]
+    fn input_kind_follows_extension() {
+        let kind = |name: &str| InputKind::from_path(Path::new(name));
+        assert_eq!(kind("slices/a.json"), Some(InputKind::Json));
+        assert_eq!(kind("slices/a.json.gz"), Some(InputKind::JsonGz));
+        assert_eq!(kind("slices/a.json.zst"), Some(InputKind::JsonZst));
+        assert_eq!(kind("slices/a.tar"), Some(InputKind::Tar));
+        assert_eq!(kind("slices/a.tar.gz"), Some(InputKind::TarGz));
+        assert_eq!(kind("slices/a.tgz"), Some(InputKind::TarGz));
+        assert_eq!(kind("slices/a.tar.zst"), Some(InputKind::TarZst));
+        assert_eq!(kind("slices/a.txt"), None);
+        assert_eq!(kind("slices/a.gz"), None);
+        assert!(InputKind::TarZst.is_archive());
+        assert!(!InputKind::JsonGz.is_archive());
+    }
+
+    #[test]
+    fn archive_members_belong_to_the_archive_project() {
+        assert_eq!(project_name("slices", "slices/projA/src/a.json"), "projA");
+        assert_eq!(
+            project_name("slices", "slices/projB.tar.gz!/src/a.json"),
+            "projB"
+        );
+        assert_eq!(
+            project_name("slices", "slices/projC.tgz!/a.json.zst"),
+            "projC"
+        );
+        assert_eq!(
+            project_name("slices", "slices/projA/all.tar!/src/a.json"),
+            "projA"
+        );
+        assert_eq!(project_name("slices", "slices/a.json"), "");
+
+        let archive = Path::new("slices/projD.tar.zst");
+        assert_eq!(input_project("slices", archive, InputKind::TarZst), "projD");
+        let file = Path::new("slices/projE/a.json.gz");
+        assert_eq!(input_project("slices", file, InputKind::JsonGz), "projE");
+    }
+
+    fn gzip(content: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn archive_members_are_read_past_a_bad_member() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut append = |name: &str, data: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data).unwrap();
+        };
+        append("src/a.json", b"{\"a\": 1}");
+        append("src/b.json.gz", b"not gzip");
+        append("README.md", b"skipped");
+        append("src/c.json.gz", &gzip("{\"c\": 3}"));
+        let data = builder.into_inner().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("extys-input-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("proj.tar");
+        std::fs::write(&path, data).unwrap();
+
+        let mut seen = Vec::new();
+        for_each_slice_file(&path, InputKind::Tar, |source, c| {
+            seen.push((source.to_owned(), c.map(str::to_owned).ok()));
+        })
+        .unwrap();
+
+        let member = |name: &str| format!("{}!/{}", path.display(), name);
+        assert_eq!(
+            seen,
+            vec![
+                (member("src/a.json"), Some("{\"a\": 1}".to_owned())),
+                (member("src/b.json.gz"), None),
+                (member("src/c.json.gz"), Some("{\"c\": 3}".to_owned())),
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
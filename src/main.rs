@@ -1,42 +1,70 @@
-#![feature(test)]
-
 #[cfg(test)]
 #[macro_use]
 extern crate lazy_static;
 
+#[cfg(all(test, feature = "alloc-bench"))]
+mod alloc_bench;
+pub mod anonymize;
+pub mod augment;
 #[cfg(test)]
 pub mod bench;
+pub mod cache;
+pub mod decontam;
+pub mod export;
+pub mod input;
+pub mod neardup;
+pub mod siblings;
 pub mod slice_structs;
+pub mod spill;
+#[cfg(test)]
+pub mod split_props;
+pub mod stats;
+pub mod template;
+pub mod tokenizer;
 pub mod utils;
+pub mod vocab;
 
-use crate::slice_structs::ObjSlice;
-use clap::Parser;
-use glob::glob;
+use crate::anonymize::{Anonymize, Anonymizer, ProjectNames};
+use crate::augment::{AugmentMethod, Augmenter};
+use crate::cache::{ImportCache, InputResult, Lookup};
+use crate::decontam::{DecontaminationStats, Decontaminator, SliceDenyList};
+use crate::neardup::NearDupStats;
+use crate::slice_structs::{FeatureValues, Interner, JsonObjSlice, ObjSlice, Sample};
+use crate::spill::{Spill, SpillWriter};
+use crate::stats::{DatasetStats, FilterReason, FilterStats, ImportStats, RunReport};
+use clap::{CommandFactory, Parser};
 use indicatif::ProgressBar;
 use itertools::Itertools;
 use memchr::memmem;
-use serde_json;
-use std::cmp::max;
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::Read;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::rc::Rc;
 use std::time::Instant;
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Serialize)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Path to Directory with Slices
+    /// Path to Directory with Slices, may contain `.json`, `.json.gz`, `.json.zst` files and `.tar`, `.tar.gz`, `.tar.zst` archives
     #[arg(short, long)]
     slices: String,
 
-    /// Path to Directory with Slices
+    /// Path to Directory the generated dataset is written to
     #[arg(short, long, default_value = "./")]
     output_dir: String,
 
-    /// Language of the parsed Slices (typescript, python)
-    #[arg(short, long)]
+    /// Language of the parsed Slices (typescript, python, java)
+    #[arg(long)]
     language: Option<String>,
 
+    /// How much of a parameterized type is kept in the class label
+    #[arg(long, value_enum, default_value_t = utils::LabelGranularity::Base)]
+    label_granularity: utils::LabelGranularity,
+
+    /// Strip package qualifiers from JVM type and method names
+    #[arg(long, default_value_t = false)]
+    shorten_packages: bool,
+
     /// Number of how many observations an object needs to be considered
     #[arg(short, long, default_value_t = 1)]
     lower_usage_bound: usize,
@@ -44,10 +72,78 @@ struct Args {
     #[arg(short, long, default_value_t = 8)]
     upper_usage_bound: usize,
 
+    /// How objects with more usages than the upper bound are split into samples
+    #[arg(long, value_enum, default_value_t = utils::SplitStrategy::Contiguous)]
+    split_strategy: utils::SplitStrategy,
+
+    /// Number of usages shared by consecutive windows of the `sliding` strategy, defaults to half the upper bound
+    #[arg(long)]
+    split_overlap: Option<usize>,
+
+    /// Seed of the `random` split strategy
+    #[arg(long, default_value_t = 0)]
+    split_seed: u64,
+
+    /// Layout of the features: `{field}` or `{field|separator}` placeholders for variable, scope, calls, arg_tos, language, return_types, subtokens, literal_kind and siblings, `[...]` sections are omitted if their fields are empty
+    #[arg(long, default_value = template::DEFAULT_TEMPLATE)]
+    feature_template: String,
+
+    /// What happens to objects that are literals
+    #[arg(long, value_enum, default_value_t = utils::LiteralPolicy::Keep)]
+    literals: utils::LiteralPolicy,
+
+    /// Fraction of the literal objects kept with `--literals cap`
+    #[arg(long, default_value_t = 0.1)]
+    literal_fraction: f64,
+
+    /// Add the kind of the literal an object is or is defined by (string, number, ...) to the features
+    #[arg(long, default_value_t = false)]
+    literal_kind: bool,
+
+    /// Add up to this many variables of the same scope that share calls with the object
+    #[arg(long)]
+    siblings: Option<usize>,
+
+    /// Add the known types of the siblings. Unsafe for evaluation data, types of related variables hint at the label, so it cannot be combined with val or test `--splits`
+    #[arg(long, default_value_t = false, requires = "siblings")]
+    sibling_types: bool,
+
+    /// Whether usages are listed once each or in their original order with repetition counts
+    #[arg(long, value_enum, default_value_t = utils::CallOrder::Set)]
+    call_order: utils::CallOrder,
+
+    /// Split variable, scope and call names into lowercase sub-tokens, either replacing the names or as a separate field
+    #[arg(long, value_enum, default_value_t = utils::SubtokenMode::Off)]
+    subtokens: utils::SubtokenMode,
+
+    /// Identifiers replaced with placeholders in the features, comma-separated
+    #[arg(long, value_enum, value_delimiter = ',')]
+    anonymize: Vec<Anonymize>,
+
+    /// Pack usages into features of at most this many tokens instead of splitting at the upper usage bound. Usages are taken by informativeness, `--split-strategy truncate` keeps only the first feature
+    #[arg(long)]
+    token_budget: Option<usize>,
+
+    /// Tokenizer estimating the length of features for the token budget
+    #[arg(long, value_enum, default_value_t = tokenizer::TokenizerKind::Whitespace)]
+    tokenizer: tokenizer::TokenizerKind,
+
+    /// BPE merges of the `bpe` tokenizer, a `merges.txt` or Hugging Face `tokenizer.json`
+    #[arg(long)]
+    bpe_vocab: Option<String>,
+
+    /// Method names longer than this many bytes lose type assertions and arguments
+    #[arg(long, default_value_t = 48)]
+    max_method_name_len: usize,
+
     /// Number of observations per class we require to be present in the dataset
     #[arg(short, long, default_value_t = 32)]
     class_occurence_threshold: usize,
 
+    /// Relabel samples of rare classes to their nearest coarser class meeting the threshold instead of dropping them
+    #[arg(long, default_value_t = false)]
+    hierarchical_labels: bool,
+
     /// If not 0, outputs a `top_n.json` file with the most common classes in the dataset
     #[arg(short, long, default_value_t = 0)]
     top_n_classes: u16,
@@ -55,13 +151,202 @@ struct Args {
     /// Cap the size of the generated dataset at the specified number
     #[arg(short, long)]
     max_samples: Option<usize>,
+
+    /// If not 0, writes up to this many dropped records per filter reason to `filtered_samples.json`. Inputs replayed from `--cache-dir` only contribute their counts
+    #[arg(long, default_value_t = 0)]
+    filter_debug_samples: usize,
+
+    /// Number of on-disk partitions used for deduplication, only the unique samples of one partition are held in memory at a time.
+    /// Near-duplicate detection and decontamination are not bounded by this, see their options
+    #[arg(long, default_value_t = 64)]
+    spill_partitions: usize,
+
+    /// Partitions larger than this many MiB on disk are split further, which bounds the memory of deduplication.
+    /// Samples with the same feature and usage count cannot be split and always share a partition
+    #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u64).range(1..))]
+    spill_partition_mb: u64,
+
+    /// If set, removes samples whose call tokens have at least this Jaccard similarity to an earlier sample of the same class.
+    /// Keeps the call tokens and LSH band keys of every kept sample in memory, linear in the size of the deduplicated corpus
+    #[arg(long)]
+    near_dup_threshold: Option<f64>,
+
+    /// Held-out dataset whose samples must not appear in the output, either a JSON array of features such as `feature_vec.json` JSONL with `text` or `feature` fields, or `feature_records.jsonl`. Repeatable.
+    /// All held-out features are kept in memory, plus the key of every removed sample
+    #[arg(long)]
+    decontaminate: Vec<String>,
+
+    /// Also remove samples whose variable, scope and usages reach this Jaccard similarity to a held-out sample
+    #[arg(long)]
+    decontamination_threshold: Option<f64>,
+
+    /// Glob pattern of slice files to skip, matched against slice file paths and archive members. Repeatable
+    #[arg(long)]
+    exclude_slices: Vec<String>,
+
+    /// Formats the dataset is written in, comma-separated
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [export::OutputFormat::Json])]
+    format: Vec<export::OutputFormat>,
+
+    /// Compression codec of Parquet output
+    #[arg(long, value_enum, default_value_t = export::ParquetCompression::Snappy)]
+    parquet_compression: export::ParquetCompression,
+
+    /// Fractions of the train, val and test splits, e.g. `0.8,0.1,0.1`. Samples of one object always share a split
+    #[arg(long, value_delimiter = ',')]
+    splits: Vec<f64>,
+
+    /// Generate augmented training samples for classes with fewer samples than this target
+    #[arg(long)]
+    augment_target: Option<usize>,
+
+    /// Transformations used for augmentation, comma-separated
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [AugmentMethod::Shuffle, AugmentMethod::DropUsages, AugmentMethod::MaskVariable])]
+    augment_methods: Vec<AugmentMethod>,
+
+    /// Seed of the augmentation
+    #[arg(long, default_value_t = 0)]
+    augment_seed: u64,
+
+    /// Label vocabulary (`label_vocab.json` of a previous run) whose label ids are reused
+    #[arg(long)]
+    label_vocab: Option<String>,
+
+    /// What happens to labels missing from the input label vocabulary
+    #[arg(long, value_enum, default_value_t = vocab::UnknownLabels::Extend)]
+    unknown_labels: vocab::UnknownLabels,
+
+    /// Label that unknown labels are mapped to with `--unknown-labels other`
+    #[arg(long, default_value = "<other>")]
+    other_label: String,
+
+    /// Directory for caching per-file import results, only changed slice files are re-imported
+    #[arg(long)]
+    cache_dir: Option<String>,
+}
+
+/// Checks combinations of arguments that clap cannot express on its own
+fn validate_args(args: &Args) -> Result<(), clap::Error> {
+    let invalid = |message: String| {
+        Err(Args::command().error(clap::error::ErrorKind::ValueValidation, message))
+    };
+
+    if !args.splits.is_empty() {
+        if let Err(e) = export::Splits::new(&args.splits) {
+            return invalid(format!("invalid value for '--splits': {}", e));
+        }
+    }
+    if args.sibling_types && args.splits.iter().skip(1).any(|f| *f != 0.0) {
+        return invalid(
+            "'--sibling-types' leaks label information into val and test splits, build evaluation data without it".to_string(),
+        );
+    }
+    if args.augment_target.is_some() && args.augment_methods.is_empty() {
+        return invalid("'--augment-target' requires at least one '--augment-methods'".to_string());
+    }
+
+    Ok(())
+}
+
+/// Chunking of objects with more usages than the upper usage bound
+fn split_config(args: &Args) -> utils::SplitConfig {
+    utils::SplitConfig {
+        strategy: args.split_strategy,
+        threshold: args.upper_usage_bound,
+        overlap: args.split_overlap.unwrap_or(args.upper_usage_bound / 2),
+        seed: args.split_seed,
+    }
+}
+
+/// Options that change the samples generated from a slice file, changing one invalidates the cache
+fn cleaning_config(args: &Args) -> serde_json::Value {
+    serde_json::json!({
+        "language": args.language,
+        "label_granularity": args.label_granularity,
+        "shorten_packages": args.shorten_packages,
+        "lower_usage_bound": args.lower_usage_bound,
+        "upper_usage_bound": args.upper_usage_bound,
+        "split": format!("{:?}", split_config(args)),
+        "exclude_slices": args.exclude_slices,
+        "anonymize": args.anonymize,
+        "feature_template": args.feature_template,
+        "subtokens": args.subtokens,
+        "literals": args.literals,
+        "literal_fraction": args.literal_fraction,
+        "literal_kind": args.literal_kind,
+        "siblings": args.siblings,
+        "sibling_types": args.sibling_types,
+        "call_order": args.call_order,
+        "records": args.format.contains(&export::OutputFormat::Records),
+        "token_budget": args.token_budget,
+        "tokenizer": args.tokenizer,
+        "bpe_vocab": args.bpe_vocab,
+        "max_method_name_len": args.max_method_name_len,
+    })
 }
 
-/// Imports slice information from json files generated by joern-slice
-fn import_slices(args: &Args) -> Vec<ObjSlice> {
-    let mut slice_candidates = Vec::new();
-    let mut num_scopes: u32 = 0;
-    let mut num_obj: u32 = 0;
+/// Parses the feature template and appends the sections of enabled features it does not place
+fn feature_template(args: &Args) -> template::FeatureTemplate {
+    let parse = |source: &str| {
+        template::FeatureTemplate::parse(source).expect("Failed to parse feature template")
+    };
+
+    let mut source = args.feature_template.to_owned();
+    let given = parse(&source);
+    if args.literal_kind && !given.uses(template::Field::LiteralKind) {
+        source.push_str(template::LITERAL_KIND_SECTION);
+    }
+    if args.siblings.is_some() && !given.uses(template::Field::Siblings) {
+        source.push_str(template::SIBLING_SECTION);
+    }
+    if args.subtokens == utils::SubtokenMode::Field && !given.uses(template::Field::Subtokens) {
+        source.push_str(template::SUBTOKEN_SECTION);
+    }
+    parse(&source)
+}
+
+/// Debug representation of an object dropped during import
+fn dropped_obj_record(source: &str, scope: &str, obj: &JsonObjSlice) -> serde_json::Value {
+    serde_json::json!({
+        "source": source,
+        "scope": scope,
+        "name": obj.target_obj.name,
+        "type": obj.target_obj.type_full_name,
+        "invoked_calls": obj.invoked_calls.iter().map(|c| &c.call_name).collect::<Vec<_>>(),
+        "arg_to_calls": obj.arg_to_calls.iter().map(|c| &c.0.call_name).collect::<Vec<_>>(),
+    })
+}
+
+/// Debug representation of an imported slice dropped during vectorization
+fn dropped_slice_record(
+    slice: &ObjSlice,
+    calls: &[String],
+    arg_tos: &[String],
+) -> serde_json::Value {
+    serde_json::json!({
+        "scope": slice.scope,
+        "name": slice.name,
+        "type": slice.type_name,
+        "calls": calls,
+        "arg_tos": arg_tos,
+    })
+}
+
+/// Imports slice information from json files generated by joern-slice. Files are processed one at a
+/// time: every object is vectorized right away and the resulting samples are spilled to disk.
+fn import_slices(
+    args: &Args,
+    parser: &utils::Parser,
+    spill: &mut SpillWriter,
+    mut cache: Option<&mut ImportCache>,
+    deny_list: &mut SliceDenyList,
+    filter_stats: &mut FilterStats,
+    report: &mut RunReport,
+) {
+    let mut num_files: usize = 0;
+    let mut num_candidates: usize = 0;
+    let mut num_scopes: usize = 0;
+    let mut num_obj: usize = 0;
 
     println!("[*] Processing slices from '{}'.", args.slices);
     let t0 = Instant::now();
@@ -70,256 +355,948 @@ fn import_slices(args: &Args) -> Vec<ObjSlice> {
     let finder_struct = memmem::Finder::new("{");
     let finder_init = memmem::Finder::new(" = new ");
 
-    let mut paths: Vec<std::path::PathBuf> = Vec::with_capacity(400_000);
-    for entry in glob(&format!("{}/**/*.json", args.slices))
-        .expect("Failed to read provided slice path as glob pattern")
-    {
-        match entry {
-            Ok(path) => paths.push(path),
-            Err(e) => println!("{:?}", e),
-        }
-    }
-
-    let num_files = paths.len();
+    let inputs = input::find_slice_inputs(&args.slices);
     println!(
-        "[*] Found {} slice files. This might take a while...",
-        num_files
+        "[*] Found {} slice inputs. This might take a while...",
+        inputs.len()
     );
 
-    // iterate over slice files
-    let bar = ProgressBar::new(num_files as _);
-    for path in paths {
-        // println!("{:?}", path);
+    let project_names = if args.anonymize.contains(&Anonymize::Calls) {
+        println!("[*] Collecting project names");
+        anonymize::collect_project_names(&args.slices, &inputs, deny_list, parser.lang)
+    } else {
+        HashMap::new()
+    };
+    let no_names = ProjectNames::default();
+    let names_digests: HashMap<&String, String> = project_names
+        .iter()
+        .map(|(project, names)| (project, names.digest()))
+        .collect();
+
+    // iterate over slice files, archives may contain many of them
+    let bar = ProgressBar::new(inputs.len() as _);
+    for (path, kind) in inputs {
+        bar.inc(1);
 
-        let mut c = String::new();
-        File::open(path).unwrap().read_to_string(&mut c).unwrap();
-        if c.is_empty() {
+        if deny_list.check(&path.display().to_string()) {
             continue;
         }
 
-        // parse slice file as json
-        let curr_slice_json: slice_structs::FullSlice =
-            serde_json::from_str(&c).expect("Failed to parse JSON file");
+        // unchanged inputs are replayed from the cache, as long as the names of their project are
+        let names_digest = names_digests
+            .get(&input::input_project(&args.slices, &path, kind))
+            .map_or("", |digest| digest.as_str());
+        let key = match cache.as_deref_mut().map(|c| c.lookup(&path, names_digest)) {
+            Some(Ok(Lookup::Hit(result))) => {
+                for sample in &result.samples {
+                    spill.push(sample);
+                }
+                for (reason, n) in result.filter_counts {
+                    filter_stats.record_n(reason, n);
+                }
+                for (pattern, n) in result.excluded_slices {
+                    *deny_list.counts.entry(pattern).or_insert(0) += n;
+                }
+                num_files += result.num_files;
+                num_scopes += result.num_scopes;
+                num_obj += result.num_objects;
+                num_candidates += result.num_candidates;
+                continue;
+            }
+            Some(Ok(Lookup::Miss(key))) => Some(key),
+            Some(Err(e)) => {
+                println!("[!] Failed to read slice input '{}': {}", path.display(), e);
+                continue;
+            }
+            None => None,
+        };
+
+        let counts_before = key
+            .as_ref()
+            .map(|_| (filter_stats.counts.clone(), deny_list.counts.clone()));
+        let mut result = InputResult::default();
+        let res = input::for_each_slice_file(&path, kind, |source, c| {
+            if kind.is_archive() && deny_list.check(source) {
+                return;
+            }
+            result.num_files += 1;
+            let c = match c {
+                Ok(c) => c,
+                Err(e) => {
+                    println!("[!] Failed to read slice file '{}': {}", source, e);
+                    filter_stats.record(
+                        FilterReason::InvalidSliceFile,
+                        || serde_json::json!({ "source": source, "error": e.to_string() }),
+                    );
+                    return;
+                }
+            };
+            if c.is_empty() {
+                return;
+            }
 
-        // iterate over scopes in file
-        for (scope, vars) in curr_slice_json.object_slices {
-            num_scopes += 1;
+            // parse slice file as json, archives may also contain other JSON files
+            let curr_slice_json: slice_structs::FullSlice = match serde_json::from_str(c) {
+                Ok(slice) => slice,
+                Err(e) => {
+                    println!("[!] Failed to parse slice file '{}': {}", source, e);
+                    filter_stats.record(
+                        FilterReason::InvalidSliceFile,
+                        || serde_json::json!({ "source": source, "error": e.to_string() }),
+                    );
+                    return;
+                }
+            };
 
-            // iterate over objects in scope
-            for curr_obj in vars {
-                num_obj += 1;
+            let project = input::project_name(&args.slices, source);
+            let names = project_names.get(&project).unwrap_or(&no_names);
 
-                let mut curr_type_name: &str = &curr_obj.target_obj.type_full_name;
+            // call, type and scope names repeat within a file and are only stored once
+            let mut interner = Interner::default();
 
-                if curr_type_name.is_empty()
-                    || curr_obj.invoked_calls.len() + curr_obj.arg_to_calls.len()
-                        < args.lower_usage_bound as usize
-                    || finder_lambda.find(curr_type_name.as_bytes()).is_some()
-                    || finder_struct.find(curr_type_name.as_bytes()).is_some()
-                {
-                    continue;
-                }
+            // iterate over scopes in file
+            for (scope, vars) in curr_slice_json.object_slices {
+                result.num_scopes += 1;
 
-                // try to recover type name from constructor call
-                if curr_type_name.eq("ANY") {
-                    if curr_obj.arg_to_calls.len() != 0 {
-                        let maybe_init_call = &curr_obj.arg_to_calls[0].0.call_name;
+                let func_scope = interner.intern(&utils::extract_scope_name(parser.lang, &scope));
+                let mut siblings = match args.siblings {
+                    Some(k) => siblings::select(parser, &vars, k, args.sibling_types),
+                    None => Vec::new(),
+                };
 
-                        match finder_init.find(maybe_init_call.as_bytes()) {
-                            Some(i) => curr_type_name = &maybe_init_call[i + 7..],
-                            None => continue,
-                        }
+                // iterate over objects in scope
+                for (i, curr_obj) in vars.iter().enumerate() {
+                    result.num_objects += 1;
+
+                    let mut curr_type_name: &str = &curr_obj.target_obj.type_full_name;
+
+                    let filter_reason = if curr_type_name.is_empty() {
+                        Some(FilterReason::EmptyType)
+                    } else if curr_obj.invoked_calls.len() + curr_obj.arg_to_calls.len()
+                        < args.lower_usage_bound
+                    {
+                        Some(FilterReason::TooFewUsages)
+                    } else if finder_lambda.find(curr_type_name.as_bytes()).is_some() {
+                        Some(FilterReason::LambdaType)
+                    } else if finder_struct.find(curr_type_name.as_bytes()).is_some() {
+                        Some(FilterReason::StructuralType)
                     } else {
+                        None
+                    };
+
+                    if let Some(reason) = filter_reason {
+                        filter_stats
+                            .record(reason, || dropped_obj_record(source, &scope, curr_obj));
                         continue;
                     }
+
+                    // try to recover type name from constructor call
+                    if curr_type_name.eq("ANY") {
+                        let recovered = curr_obj.arg_to_calls.first().and_then(|c| {
+                            let maybe_init_call = &c.0.call_name;
+                            finder_init
+                                .find(maybe_init_call.as_bytes())
+                                .map(|i| &maybe_init_call[i + 7..])
+                        });
+
+                        match recovered {
+                            Some(type_name) => curr_type_name = type_name,
+                            None => {
+                                filter_stats.record(FilterReason::UnrecoverableAny, || {
+                                    dropped_obj_record(source, &scope, curr_obj)
+                                });
+                                continue;
+                            }
+                        }
+                    }
+
+                    let mut curr_slice =
+                        ObjSlice::new(curr_obj, func_scope.clone(), curr_type_name, &mut interner);
+                    if let Some(obj_siblings) = siblings.get_mut(i) {
+                        curr_slice.siblings = std::mem::take(obj_siblings);
+                    }
+                    result.num_candidates += 1;
+
+                    // println!("Slice: {:?}\n", curr_slice);
+                    for sample in vectorize_slice(
+                        args,
+                        parser,
+                        &project,
+                        source,
+                        names,
+                        curr_slice,
+                        filter_stats,
+                    ) {
+                        spill.push(&sample);
+                        if key.is_some() {
+                            result.samples.push(sample);
+                        }
+                    }
                 }
+            }
+        });
 
-                let func_scope = utils::extract_func_name(&scope);
-                let curr_slice = slice_structs::ObjSlice {
-                    name: curr_obj.target_obj.name,
-                    scope: func_scope,
-                    type_name: curr_type_name.to_string(),
-                    invoked_calls: curr_obj.invoked_calls,
-                    arg_to_calls: curr_obj.arg_to_calls,
-                };
+        num_files += result.num_files;
+        num_scopes += result.num_scopes;
+        num_obj += result.num_objects;
+        num_candidates += result.num_candidates;
 
-                // println!("Slice: {:?}\n", curr_slice);
-                slice_candidates.push(curr_slice);
+        match (res, cache.as_deref_mut(), key, counts_before) {
+            (Err(e), _, _, _) => {
+                println!("[!] Failed to read slice input '{}': {}", path.display(), e);
+                filter_stats.record(FilterReason::InvalidSliceFile, || {
+                    serde_json::json!({ "source": path.display().to_string(), "error": e.to_string() })
+                });
+            }
+            (Ok(()), Some(cache), Some(key), Some(before)) => {
+                result.filter_counts = cache::count_delta(&before.0, &filter_stats.counts);
+                result.excluded_slices = cache::count_delta(&before.1, &deny_list.counts);
+                cache.store(&path, key, &result);
             }
+            _ => {}
         }
-
-        bar.inc(1);
     }
     bar.finish();
     println!(
         "[i] Importing slices took {:.3}s",
         t0.elapsed().as_secs_f32()
     );
+
+    let cached_inputs = cache.as_deref().map_or(0, |c| c.hits);
+    if let Some(cache) = cache {
+        println!(
+            "[i] Reused cached results for {} of {} slice inputs",
+            cache.hits,
+            cache.hits + cache.misses
+        );
+    }
+
+    report.import = ImportStats {
+        num_files,
+        num_scopes,
+        num_objects: num_obj,
+        num_candidates,
+        cached_inputs,
+        scopes_per_file: num_scopes as f32 / num_files as f32,
+        candidates_per_file: num_candidates as f32 / num_files as f32,
+        objects_per_file: num_obj as f32 / num_files as f32,
+        candidate_percentage: num_candidates as f32 / num_obj as f32 * 100.0,
+    };
     println!(
         "[i] Found an average of {:.2} scopes in {} slice files",
-        num_scopes as f32 / num_files as f32,
-        num_files
+        report.import.scopes_per_file, num_files
     );
     println!(
         "[i] Found {:?} slice candidates ({:.1}% of {} total)",
-        slice_candidates.len(),
-        slice_candidates.len() as f32 / num_obj as f32 * 100.0,
-        num_obj
+        num_candidates, report.import.candidate_percentage, num_obj
     );
     println!(
         "    - average per file:                  {:.2}",
-        slice_candidates.len() as f32 / num_files as f32
+        report.import.candidates_per_file
     );
     println!(
         "    - average of total objects per file: {:.2}",
-        num_obj as f32 / num_files as f32
+        report.import.objects_per_file
     );
-
-    slice_candidates
 }
 
-/// Performs filtering, denoising and vectorization of slices and its field
-fn vectorize_slices(args: &Args, slices: Vec<ObjSlice>) {
-    println!("[*] Begin Vectorizing Slices");
-    let t0 = Instant::now();
+/// Performs filtering, denoising and vectorization of a single slice and its fields
+fn vectorize_slice(
+    args: &Args,
+    parser: &utils::Parser,
+    project: &str,
+    source: &str,
+    names: &ProjectNames,
+    mut curr_slice: ObjSlice,
+    filter_stats: &mut FilterStats,
+) -> Vec<Sample> {
+    let mut samples = Vec::new();
 
-    let parser = utils::Parser::new(&args.language);
-    let mut candidates: Vec<(String, String, usize)> = Vec::new();
-
-    let bar = ProgressBar::new(slices.len() as _);
-    for mut curr_slice in slices {
-        if let Some(i) = parser.finder_colon.find(curr_slice.name.as_bytes()) {
-            curr_slice.name = curr_slice.name[..i].to_string();
+    if curr_slice.literal {
+        let dropped = match args.literals {
+            utils::LiteralPolicy::Keep => false,
+            utils::LiteralPolicy::Drop => true,
+            utils::LiteralPolicy::Cap => {
+                utils::stable_fraction(&[project, &curr_slice.scope, &curr_slice.name])
+                    >= args.literal_fraction
+            }
+        };
+        if dropped {
+            filter_stats.record(FilterReason::Literal, || {
+                dropped_slice_record(&curr_slice, &[], &[])
+            });
+            return samples;
         }
+    }
+
+    if let Some(i) = parser.finder_colon.find(curr_slice.name.as_bytes()) {
+        curr_slice.name = curr_slice.name[..i].to_string();
+    }
 
-        let calls: Vec<String> = curr_slice
+    let mut calls: Vec<String> = if parser.lang == utils::Language::Java {
+        curr_slice
             .invoked_calls
-            .clone()
-            .into_iter()
-            .map(|c| c.call_name)
-            .unique()
-            .collect();
+            .iter()
+            .filter_map(|c| utils::clean_method_name(parser, c))
+            .collect()
+    } else {
+        curr_slice
+            .invoked_calls
+            .iter()
+            .map(|c| c.to_string())
+            .collect()
+    };
+
+    let mut arg_tos: Vec<String> = Vec::with_capacity(curr_slice.arg_to_calls.len());
+    for c in &curr_slice.arg_to_calls {
+        if let Some(call_name) = utils::clean_method_name(parser, &c.0) {
+            // if let Some(recv) = &curr_call.receiver {
+            //     if !(recv.eq("this") || recv.starts_with("_tmp_") || recv.eq("_")) {
+            //         call_name = format!("{}.{}", recv, call_name);
+            //     }
+            // }
+
+            arg_tos.push(call_name);
+        }
+    }
+
+    let set_semantics = args.call_order == utils::CallOrder::Set;
+    if set_semantics {
+        calls = calls.into_iter().unique().collect();
+        arg_tos = arg_tos.into_iter().unique().collect();
+    }
 
-        let mut arg_tos: Vec<String> = Vec::with_capacity(curr_slice.arg_to_calls.len());
+    // argument positions per call, aligned with `arg_tos`, are only needed for records
+    let records = args.format.contains(&export::OutputFormat::Records);
+    let positions: Vec<Vec<i32>> = if records {
+        let mut by_name: HashMap<String, Vec<i32>> = HashMap::new();
         for c in &curr_slice.arg_to_calls {
-            let curr_call = &c.0;
+            if let Some(call_name) = utils::clean_method_name(parser, &c.0) {
+                let positions = by_name.entry(call_name).or_default();
+                if !positions.contains(&c.1) {
+                    positions.push(c.1);
+                }
+            }
+        }
+        arg_tos
+            .iter()
+            .map(|a| by_name.get(a).cloned().unwrap_or_default())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // if we only observe a single assignment, this variable is not interesting
+    if calls.is_empty()
+        && arg_tos.iter().unique().count() == 1
+        && arg_tos[0].starts_with("assignment")
+    {
+        filter_stats.record(FilterReason::SingleAssignment, || {
+            dropped_slice_record(&curr_slice, &calls, &arg_tos)
+        });
+        return samples;
+    }
+
+    // provenance keeps the original names
+    let scope = curr_slice.scope.to_string();
+    let variable = curr_slice.name.to_owned();
+    if !args.anonymize.is_empty() {
+        Anonymizer::new(&args.anonymize, parser.lang, names).apply(
+            &mut curr_slice,
+            &mut calls,
+            &mut arg_tos,
+        );
+    }
+
+    let return_types: Vec<String> = if parser.feature_template.uses(template::Field::ReturnTypes) {
+        curr_slice
+            .return_types
+            .iter()
+            .filter(|t| !t.is_empty() && t.as_ref() != "ANY")
+            .map(|t| utils::merge_synonyms(parser, &utils::type_label(parser, t)))
+            .unique()
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut arg_positions: HashMap<String, Vec<i32>> = HashMap::new();
+    if args.subtokens == utils::SubtokenMode::Inline {
+        curr_slice.name = utils::subtokenize(&curr_slice.name);
+        // scope components stay separated
+        let scope = curr_slice
+            .scope
+            .split("::")
+            .map(utils::subtokenize)
+            .join("::");
+        curr_slice.scope = Rc::from(scope);
+        for sibling in &mut curr_slice.siblings {
+            *sibling = match sibling.split_once(": ") {
+                Some((name, type_name)) => format!("{}: {}", utils::subtokenize(name), type_name),
+                None => utils::subtokenize(sibling),
+            };
+        }
+        for call in calls.iter_mut().chain(arg_tos.iter_mut()) {
+            *call = utils::subtokenize(call);
+        }
 
-            if let Some(call_name) = utils::clean_method_name(&parser, &curr_call.call_name) {
-                // if let Some(recv) = &curr_call.receiver {
-                //     if !(recv.eq("this") || recv.starts_with("_tmp_") || recv.eq("_")) {
-                //         call_name = format!("{}.{}", recv, call_name);
-                //     }
-                // }
+        // different spellings of a name may end up with the same sub-tokens
+        if set_semantics {
+            calls = calls.into_iter().unique().collect();
+        }
+        for (call, call_positions) in arg_tos.iter().zip(positions) {
+            let merged = arg_positions.entry(call.to_owned()).or_default();
+            for position in call_positions {
+                if !merged.contains(&position) {
+                    merged.push(position);
+                }
+            }
+        }
+        if set_semantics {
+            arg_tos = arg_tos.into_iter().unique().collect();
+        }
+    } else {
+        arg_positions = arg_tos.iter().cloned().zip(positions).collect();
+    }
+
+    // repetitions are counted once names are final, positions stay keyed by the plain names
+    if !set_semantics {
+        calls = utils::run_lengths(&calls);
+        arg_tos = utils::run_lengths(&arg_tos);
+    }
+
+    let total_usages = utils::num_usages(&calls, &arg_tos, args.call_order);
+    if total_usages >= args.lower_usage_bound {
+        let type_name = utils::type_label(parser, &curr_slice.type_name);
+        let label = utils::merge_synonyms(parser, &type_name);
 
-                arg_tos.push(call_name);
+        // generate multiple samples from one usage slice if it is too long
+        let (splits, split_strategy) = if let Some(budget) = &parser.token_budget {
+            let count = |c: &[String], a: &[String]| {
+                let feature = utils::assemble(
+                    &parser.feature_template,
+                    &curr_slice,
+                    c,
+                    a,
+                    &return_types,
+                    &args.language,
+                    args.call_order,
+                );
+                budget.tokenizer.count(&feature)
+            };
+
+            if count(&calls, &arg_tos) <= budget.max_tokens {
+                (vec![(calls, arg_tos)], None)
+            } else {
+                let splits =
+                    utils::pack_usages(&calls, &arg_tos, args.split_strategy, budget, count);
+                if splits.is_empty() {
+                    filter_stats.record(FilterReason::TokenBudget, || {
+                        dropped_slice_record(&curr_slice, &calls, &arg_tos)
+                    });
+                }
+                (splits, Some(args.split_strategy))
             }
+        } else if total_usages > args.upper_usage_bound {
+            let config = split_config(args);
+            // chunks are made of entries, the repetitions of a sequence may fit as a whole
+            let split = calls.len() + arg_tos.len() > config.threshold.max(1);
+            let splits = utils::split_usages(calls, arg_tos, &config);
+            (splits, split.then_some(config.strategy))
+        } else {
+            (vec![(calls, arg_tos)], None)
+        };
+
+        for (chunk, s) in splits.into_iter().enumerate() {
+            let values = FeatureValues {
+                variable: curr_slice.name.to_owned(),
+                scope: curr_slice.scope.to_string(),
+                calls: s.0,
+                arg_tos: s.1,
+                return_types: return_types.clone(),
+                literal_kind: curr_slice.literal_kind.map(String::from),
+                siblings: curr_slice.siblings.clone(),
+            };
+            let record = records.then(|| {
+                let record = utils::feature_record(
+                    &curr_slice,
+                    &values.calls,
+                    &values.arg_tos,
+                    &arg_positions,
+                    &args.language,
+                    args.call_order,
+                );
+                match args.subtokens {
+                    utils::SubtokenMode::Field => record.with_subtokens(args.call_order),
+                    _ => record,
+                }
+            });
+            samples.push(Sample {
+                feature: utils::render(
+                    &parser.feature_template,
+                    &values,
+                    &args.language,
+                    args.call_order,
+                ),
+                label: label.to_owned(),
+                num_usages: utils::num_usages(&values.calls, &values.arg_tos, args.call_order),
+                values,
+                project: project.to_string(),
+                source: source.to_string(),
+                scope: scope.to_owned(),
+                variable: variable.to_owned(),
+                split_strategy,
+                chunk,
+                augmentation: None,
+                literal: curr_slice.literal,
+                record,
+            });
         }
+    } else {
+        filter_stats.record(FilterReason::TooFewUsages, || {
+            dropped_slice_record(&curr_slice, &calls, &arg_tos)
+        });
+    }
 
-        arg_tos = arg_tos.into_iter().unique().collect();
+    samples
+}
+
+/// Loads a spilled partition without duplicates. If a hierarchy `mapping` is given, samples are
+/// relabeled and samples whose label has no mapping are returned separately.
+fn dedup_partition(
+    partition: impl Iterator<Item = Sample>,
+    mapping: Option<&HashMap<String, String>>,
+) -> (HashSet<Sample>, Vec<Sample>) {
+    let exact: HashSet<Sample> = partition.collect();
 
-        // if we only observe a single assignment, this variable is not interesting
-        if calls.len() == 0 && arg_tos.len() == 1 {
-            if arg_tos[0].starts_with("assignment") {
+    let mapping = match mapping {
+        Some(m) => m,
+        None => return (exact, Vec::new()),
+    };
+
+    let mut unique = HashSet::with_capacity(exact.len());
+    let mut unmapped = Vec::new();
+    for mut sample in exact {
+        match mapping.get(&sample.label) {
+            Some(label) => {
+                sample.label = label.to_owned();
+                unique.insert(sample);
+            }
+            None => unmapped.push(sample),
+        }
+    }
+
+    (unique, unmapped)
+}
+
+/// Counts the occurrences of each class among the deduplicated spilled samples
+fn count_classes(
+    spill: &Spill,
+    mapping: Option<&HashMap<String, String>>,
+    excluded: Option<&HashSet<u64>>,
+) -> HashMap<String, usize> {
+    let mut class_counts = HashMap::new();
+
+    let bar = ProgressBar::new(spill.num_partitions() as _);
+    for partition in spill.partitions() {
+        for sample in dedup_partition(partition, mapping).0 {
+            if excluded.is_some_and(|e| e.contains(&neardup::sample_key(&sample))) {
                 continue;
             }
+            *class_counts.entry(sample.label).or_insert(0) += 1;
         }
+        bar.inc(1);
+    }
+    bar.finish();
+
+    class_counts
+}
+
+/// Finds the keys of samples that match a sample of a held-out dataset and, with a `detector`, of
+/// samples that are near duplicates of an earlier sample. The classes of the remaining samples are
+/// counted in the same pass. Partitions are visited in order and sorted, so which sample of a
+/// near-duplicate cluster is kept does not change between runs.
+fn find_excluded(
+    spill: &Spill,
+    mapping: Option<&HashMap<String, String>>,
+    mut decontaminator: Option<&mut Decontaminator>,
+    mut detector: Option<&mut neardup::NearDupDetector>,
+) -> (HashSet<u64>, HashMap<String, usize>) {
+    let mut contaminated = HashSet::new();
+    let mut class_counts = HashMap::new();
 
-        let total_usages = calls.len() + arg_tos.len();
-        if total_usages >= args.lower_usage_bound {
-            curr_slice.type_name = utils::clean_type(&parser, &curr_slice.type_name)[0].to_owned();
-
-            // generate multiple samples from one usage slice if it is too long
-            if total_usages > args.upper_usage_bound {
-                let splits = utils::generate_splits(calls, arg_tos, args.upper_usage_bound);
-                for s in splits {
-                    let feat_str = utils::assemble(&curr_slice, &(s.0), &(s.1), &args.language);
-                    candidates.push((
-                        feat_str,
-                        curr_slice.type_name.to_owned(),
-                        s.0.len() + s.1.len(),
-                    ));
+    let bar = ProgressBar::new(spill.num_partitions() as _);
+    for partition in spill.partitions() {
+        let mut unique: Vec<Sample> = dedup_partition(partition, mapping).0.into_iter().collect();
+        if detector.is_some() {
+            unique.sort_by(|a, b| {
+                (&a.label, &a.feature, a.num_usages).cmp(&(&b.label, &b.feature, b.num_usages))
+            });
+        }
+        for sample in unique {
+            if let Some(decontaminator) = decontaminator.as_deref_mut() {
+                if decontaminator.check(&sample) {
+                    contaminated.insert(neardup::sample_key(&sample));
+                    continue;
+                }
+            }
+            if let Some(detector) = detector.as_deref_mut() {
+                if detector.check(&sample) {
+                    continue;
                 }
-            } else {
-                let feat_str = utils::assemble(&curr_slice, &calls, &arg_tos, &args.language);
-                candidates.push((
-                    feat_str,
-                    curr_slice.type_name.to_owned(),
-                    calls.len() + arg_tos.len(),
-                ));
             }
+            *class_counts.entry(sample.label).or_insert(0) += 1;
         }
-
         bar.inc(1);
     }
     bar.finish();
 
+    (contaminated, class_counts)
+}
+
+/// Near-duplicate statistics, the kept samples of the largest clusters are looked up for the report
+fn near_dup_stats(
+    spill: &Spill,
+    mapping: Option<&HashMap<String, String>>,
+    detector: &neardup::NearDupDetector,
+) -> NearDupStats {
+    let (mut stats, largest) = detector.stats();
+    if !largest.is_empty() {
+        for partition in spill.partitions() {
+            for sample in dedup_partition(partition, mapping).0 {
+                if let Some(size) = largest.get(&neardup::sample_key(&sample)) {
+                    stats.largest_clusters.push(neardup::Cluster {
+                        size: *size,
+                        label: sample.label,
+                        feature: sample.feature,
+                    });
+                }
+            }
+        }
+        stats
+            .largest_clusters
+            .sort_by_key(|c| std::cmp::Reverse(c.size));
+    }
+
+    stats
+}
+
+/// Deduplicates the spilled samples, applies the class threshold and sample cap and streams the
+/// remaining samples into the dataset. Returns the set of classes in the final dataset.
+fn finalize_samples(
+    args: &Args,
+    parser: &utils::Parser,
+    spill: Spill,
+    decontaminator: Option<&mut Decontaminator>,
+    filter_stats: &mut FilterStats,
+    report: &mut RunReport,
+) -> HashSet<String> {
     println!("[*] Deduplication and Generation of Type Histograms");
-    let mut unq_candidates = candidates
-        .into_iter()
-        .collect::<HashSet<_>>()
-        .into_iter()
-        .collect::<Vec<_>>();
+    let t0 = Instant::now();
 
-    utils::merge_common_types(&mut unq_candidates);
+    // first pass over the spilled samples: class histogram
+    let mut class_counts = count_classes(&spill, None, None);
 
-    let mut class_counts = HashMap::new();
+    let mapping = if args.hierarchical_labels {
+        println!("[*] Relabeling rare classes to their nearest coarser class");
+        let mapping = utils::resolve_label_hierarchy(&class_counts, args.class_occurence_threshold);
+        class_counts = count_classes(&spill, Some(&mapping), None);
+        Some(mapping)
+    } else {
+        None
+    };
 
-    // count occurrences of each class
-    for (_, class, _) in &unq_candidates {
-        let curr_type = class.to_owned();
-        *class_counts.entry(curr_type).or_insert(0) += 1;
+    // samples matching held-out datasets and near duplicates are excluded before thresholding
+    let mut contaminated = HashSet::new();
+    let mut near_dups = HashSet::new();
+    if decontaminator.is_some() || args.near_dup_threshold.is_some() {
+        if decontaminator.is_some() {
+            println!("[*] Removing samples matching held-out datasets");
+        }
+        if args.near_dup_threshold.is_some() {
+            println!("[*] Detecting near-duplicate samples");
+        }
+        let mut detector = args
+            .near_dup_threshold
+            .map(|threshold| neardup::NearDupDetector::new(threshold, args.language.clone()));
+        (contaminated, class_counts) =
+            find_excluded(&spill, mapping.as_ref(), decontaminator, detector.as_mut());
+        if !args.decontaminate.is_empty() {
+            println!(
+                "[i] Found {} samples matching held-out samples",
+                contaminated.len()
+            );
+        }
+
+        if let Some(detector) = detector {
+            let stats = near_dup_stats(&spill, mapping.as_ref(), &detector);
+            println!(
+                "[i] Found {} near-duplicate samples in {} clusters, largest clusters:",
+                stats.removed,
+                stats
+                    .cluster_sizes
+                    .iter()
+                    .filter(|(size, _)| **size > 1)
+                    .map(|(_, n)| n)
+                    .sum::<usize>()
+            );
+            for cluster in &stats.largest_clusters {
+                println!(
+                    "    - {:>8} x {} ({})",
+                    cluster.size, cluster.feature, cluster.label
+                );
+            }
+
+            report.near_duplicates = Some(stats);
+            near_dups = detector.duplicates;
+        }
     }
 
-    unq_candidates.retain(|(_, class, _)| {
-        class_counts.get(class).unwrap_or(&0) >= &args.class_occurence_threshold
+    let mut quotas = args
+        .max_samples
+        .map(|n| utils::sample_quotas(&class_counts, args.class_occurence_threshold, n));
+
+    // classes that make it into the dataset, new ids are assigned in lexicographic order
+    let classes = class_counts.iter().filter(|(label, n)| {
+        **n >= args.class_occurence_threshold
+            && quotas
+                .as_ref()
+                .is_none_or(|q| q.get(*label).unwrap_or(&0) > &0)
     });
+    let vocab = match &args.label_vocab {
+        Some(path) => {
+            let (vocab, changes) = vocab::LabelVocab::load(
+                path,
+                classes.map(|(label, _)| label),
+                args.unknown_labels,
+                &args.other_label,
+            );
+            println!(
+                "[i] Label vocabulary: {} labels added, {} removed, {} unknown",
+                changes.added.len(),
+                changes.removed.len(),
+                changes.unknown.len()
+            );
+            report.vocab_changes = Some(changes);
+            vocab
+        }
+        None => vocab::LabelVocab::new(classes.map(|(label, _)| label)),
+    };
 
-    // TODO: optimize
-    // resize the vector while maintaining the distribution of observed classes
-    if let Some(n) = args.max_samples {
-        let mut filtered_candidates = Vec::new();
-        let mut count_vec: Vec<_> = class_counts.iter().collect();
-        count_vec.sort_by(|a, b| b.1.cmp(a.1));
+    let splits = (!args.splits.is_empty())
+        .then(|| export::Splits::new(&args.splits).expect("Failed to validate split fractions"));
 
-        let cut_off = count_vec
-            .iter()
-            .position(|&c| c.1 < &args.class_occurence_threshold)
-            .unwrap();
+    // second pass: apply thresholds and stream samples into the dataset
+    println!("[*] Writing dataset to '{}'", args.output_dir);
+    let mut writer = args
+        .format
+        .contains(&export::OutputFormat::Json)
+        .then(|| utils::DatasetWriter::create(&args.output_dir, ""));
+    // augmented samples are untagged in JSON, so they get their own files
+    let mut augmented_writer = (writer.is_some() && args.augment_target.is_some())
+        .then(|| utils::DatasetWriter::create(&args.output_dir, "_augmented"));
+    let mut parquet = args
+        .format
+        .contains(&export::OutputFormat::Parquet)
+        .then(|| {
+            export::ParquetWriter::create(
+                &args.output_dir,
+                utils::Language::from_arg(&args.language).name(),
+                splits.as_ref(),
+                args.parquet_compression,
+            )
+        });
+    let mut hf = args.format.contains(&export::OutputFormat::Hf).then(|| {
+        export::HfWriter::create(
+            &args.output_dir,
+            splits.as_ref(),
+            args.augment_target.is_some(),
+        )
+    });
+    let mut records = args
+        .format
+        .contains(&export::OutputFormat::Records)
+        .then(|| {
+            export::RecordWriter::create(
+                &args.output_dir,
+                utils::Language::from_arg(&args.language).name(),
+                splits.as_ref(),
+            )
+        });
+    let mut augmenter = args.augment_target.map(|target| {
+        Augmenter::new(
+            target,
+            args.augment_seed,
+            &args.augment_methods,
+            args.lower_usage_bound,
+            args.call_order,
+        )
+    });
+    let mut final_counts: HashMap<String, usize> = HashMap::new();
+    let mut split_counts: BTreeMap<&'static str, usize> = BTreeMap::new();
 
-        let mut class_to_filtered_indices = HashMap::<&String, Vec<usize>>::new();
-        for (index, (_, class, _)) in unq_candidates.iter().enumerate() {
-            if class_counts.get(class).unwrap() >= &args.class_occurence_threshold {
-                class_to_filtered_indices
-                    .entry(class)
-                    .or_insert_with(Vec::new)
-                    .push(index);
-            }
-        }
+    let bar = ProgressBar::new(spill.num_partitions() as _);
+    for partition in spill.partitions() {
+        let mut num_spilled = 0;
+        let (unique, unmapped) =
+            dedup_partition(partition.inspect(|_| num_spilled += 1), mapping.as_ref());
+        filter_stats.record_n(
+            FilterReason::Duplicate,
+            num_spilled - unique.len() - unmapped.len(),
+        );
 
-        for type_tuple in count_vec[..cut_off].iter().rev() {
-            let mut curr_count = 0;
-            let max_count = max(
-                *class_counts.get(type_tuple.0).unwrap() * n / unq_candidates.len(),
-                args.class_occurence_threshold,
+        for sample in unmapped {
+            filter_stats.record(
+                FilterReason::UnderThreshold,
+                || serde_json::json!({ "feature": sample.feature, "label": sample.label }),
             );
+        }
+
+        for mut sample in unique {
+            let key = neardup::sample_key(&sample);
+            let excluded = if contaminated.contains(&key) {
+                Some(FilterReason::Contaminated)
+            } else if near_dups.contains(&key) {
+                Some(FilterReason::NearDuplicate)
+            } else {
+                None
+            };
+            if let Some(reason) = excluded {
+                filter_stats.record(
+                    reason,
+                    || serde_json::json!({ "feature": sample.feature, "label": sample.label }),
+                );
+                continue;
+            }
+
+            if class_counts.get(&sample.label).unwrap_or(&0) < &args.class_occurence_threshold {
+                filter_stats.record(
+                    FilterReason::UnderThreshold,
+                    || serde_json::json!({ "feature": sample.feature, "label": sample.label }),
+                );
+                continue;
+            }
 
-            if let Some(indices) = class_to_filtered_indices.get(type_tuple.0) {
-                for &index in indices {
-                    let (feature, class, u) = &unq_candidates[index];
-                    filtered_candidates.push((feature.clone(), class.clone(), *u));
-                    curr_count += 1;
-                    if curr_count > max_count || filtered_candidates.len() >= n {
-                        break;
+            if let Some(quotas) = quotas.as_mut() {
+                match quotas.get_mut(&sample.label) {
+                    Some(q) if *q > 0 => *q -= 1,
+                    _ => {
+                        filter_stats.record(
+                            FilterReason::SampleCap,
+                            || serde_json::json!({ "feature": sample.feature, "label": sample.label }),
+                        );
+                        continue;
                     }
                 }
             }
 
-            if filtered_candidates.len() >= n {
-                break;
+            let label_id = match vocab.resolve(&sample.label) {
+                Some((label, id)) => {
+                    if label != sample.label {
+                        sample.label = label.to_owned();
+                    }
+                    id
+                }
+                None => {
+                    filter_stats.record(
+                        FilterReason::UnknownLabel,
+                        || serde_json::json!({ "feature": sample.feature, "label": sample.label }),
+                    );
+                    continue;
+                }
+            };
+
+            let split = splits.as_ref().map_or(0, |s| s.assign(&sample));
+            if let Some(splits) = splits.as_ref() {
+                *split_counts.entry(splits.names()[split]).or_insert(0) += 1;
+            }
+
+            if let Some(writer) = writer.as_mut() {
+                writer.write(&sample);
+            }
+            if let Some(parquet) = parquet.as_mut() {
+                parquet.write(&sample, label_id, split);
             }
+            if let Some(hf) = hf.as_mut() {
+                hf.write(&sample, label_id, split);
+            }
+            if let Some(records) = records.as_mut() {
+                records.write(&sample, label_id, split);
+            }
+            if let Some(augmenter) = augmenter.as_mut() {
+                augmenter.observe(&sample, split);
+            }
+            if sample.literal {
+                *report
+                    .literal_samples
+                    .entry(sample.label.to_owned())
+                    .or_insert(0) += 1;
+            }
+            *final_counts.entry(sample.label).or_insert(0) += 1;
         }
+        bar.inc(1);
+    }
+    bar.finish();
+
+    // augmented samples always go to the training split
+    if let Some(mut augmenter) = augmenter {
+        println!("[*] Augmenting under-represented classes");
+        let render = |values: &FeatureValues| {
+            utils::render(
+                &parser.feature_template,
+                values,
+                &args.language,
+                args.call_order,
+            )
+        };
+        for sample in augmenter.generate(&final_counts, render) {
+            let (_, label_id) = vocab.resolve(&sample.label).unwrap();
+            if let Some(splits) = splits.as_ref() {
+                *split_counts
+                    .entry(splits.names()[augment::TRAIN_SPLIT])
+                    .or_insert(0) += 1;
+            }
 
-        unq_candidates = filtered_candidates;
+            if let Some(writer) = augmented_writer.as_mut() {
+                writer.write(&sample);
+            }
+            if let Some(parquet) = parquet.as_mut() {
+                parquet.write(&sample, label_id, augment::TRAIN_SPLIT);
+            }
+            if let Some(hf) = hf.as_mut() {
+                hf.write(&sample, label_id, augment::TRAIN_SPLIT);
+            }
+            if let Some(records) = records.as_mut() {
+                records.write(&sample, label_id, augment::TRAIN_SPLIT);
+            }
+            if sample.literal {
+                *report
+                    .literal_samples
+                    .entry(sample.label.to_owned())
+                    .or_insert(0) += 1;
+            }
+            *final_counts.entry(sample.label).or_insert(0) += 1;
+        }
+
+        let stats = augmenter.stats();
+        println!(
+            "[i] Generated {} augmented samples for {} classes",
+            stats.classes.values().sum::<usize>(),
+            stats.classes.len()
+        );
+        report.augmentation = Some(stats);
+    }
+    if let Some(writer) = writer {
+        writer.finish();
+    }
+    if let Some(writer) = augmented_writer {
+        writer.finish();
+    }
+    if let Some(parquet) = parquet {
+        parquet.finish();
+    }
+    if let Some(records) = records {
+        records.finish();
     }
 
     println!(
@@ -327,39 +1304,146 @@ fn vectorize_slices(args: &Args, slices: Vec<ObjSlice>) {
         t0.elapsed().as_secs_f32()
     );
 
-    // generate stats
-    let type_set = unq_candidates
-        .clone()
-        .into_iter()
-        .map(|c| c.1)
-        .collect::<HashSet<_>>()
-        .into_iter()
-        .collect::<Vec<_>>();
-
-    let num_types = type_set.len();
-    let mut occ: Vec<usize> = type_set
-        .into_iter()
-        .map(|t| *class_counts.get(&t).unwrap())
+    vocab.persist(&args.output_dir);
+
+    // generate stats, classes merged into the `other` label only have their final count
+    let mut occ: Vec<usize> = final_counts
+        .iter()
+        .map(|(t, n)| *class_counts.get(t).unwrap_or(n))
         .collect();
     occ.sort();
 
-    println!(
-        "[i] Using {} slice candidates after filtering",
-        unq_candidates.len()
-    );
-    println!("[i] Found {} unique classes", num_types);
+    let num_samples: usize = final_counts.values().sum();
+    report.dataset = DatasetStats {
+        num_samples,
+        num_classes: final_counts.len(),
+        avg_occurrences: occ.iter().sum::<usize>() as f32 / occ.len() as f32,
+        median_occurrences: occ.get(occ.len() / 2).copied().unwrap_or(0),
+    };
+
+    println!("[i] Using {} slice candidates after filtering", num_samples);
+    println!("[i] Found {} unique classes", final_counts.len());
+    if !report.literal_samples.is_empty() {
+        println!(
+            "[i] {} samples of {} classes come from literals",
+            report.literal_samples.values().sum::<usize>(),
+            report.literal_samples.len()
+        );
+    }
+    for (split, n) in &split_counts {
+        println!("    - {:<6} {:>10} samples", split, n);
+    }
     println!(
         "[i] Occurences per type:\n    - average: {:.2}\n    - median:  {} ",
-        occ.iter().sum::<usize>() as f32 / occ.len() as f32,
-        occ[occ.len() / 2]
+        report.dataset.avg_occurrences, report.dataset.median_occurrences
     );
 
-    utils::persist_to_disk(unq_candidates);
+    let classes = final_counts.keys().cloned().collect();
+    report.split_sizes = split_counts;
+    report.class_histogram = final_counts.into_iter().collect();
+
+    if let Some(hf) = hf {
+        hf.finish(vocab.labels(), report);
+    }
+
+    classes
 }
 
 fn main() {
     let args = Args::parse();
+    if let Err(e) = validate_args(&args) {
+        e.exit();
+    }
+    run(args);
+}
+
+fn run(args: Args) {
+    let mut report = RunReport::new(&args);
+    let mut filter_stats = FilterStats::new(args.filter_debug_samples);
+
+    let mut parser = utils::Parser::new(&args.language);
+    parser.shorten_packages = args.shorten_packages;
+    parser.label_granularity = args.label_granularity;
+    parser.feature_template = feature_template(&args);
+    parser.max_name_len = args.max_method_name_len;
+    parser.token_budget = args.token_budget.map(|max_tokens| tokenizer::TokenBudget {
+        tokenizer: tokenizer::Tokenizer::new(args.tokenizer, args.bpe_vocab.as_deref())
+            .unwrap_or_else(|e| Args::command().error(clap::error::ErrorKind::Io, e).exit()),
+        max_tokens,
+    });
+
+    let t0 = Instant::now();
+    let mut spill = SpillWriter::create(
+        &Path::new(&args.output_dir).join(".extys_spill"),
+        args.spill_partitions,
+        args.spill_partition_mb * 1024 * 1024,
+    );
+    let mut cache = args
+        .cache_dir
+        .as_ref()
+        .map(|dir| ImportCache::open(dir, cleaning_config(&args)));
+    let mut deny_list = SliceDenyList::new(&args.exclude_slices);
+    import_slices(
+        &args,
+        &parser,
+        &mut spill,
+        cache.as_mut(),
+        &mut deny_list,
+        &mut filter_stats,
+        &mut report,
+    );
+    if let Some(cache) = cache {
+        cache.finish();
+    }
+    report.timings.insert("import", t0.elapsed().as_secs_f32());
+
+    let t0 = Instant::now();
+    let mut decontaminator = (!args.decontaminate.is_empty()).then(|| {
+        Decontaminator::load(
+            &args.decontaminate,
+            args.decontamination_threshold,
+            args.language.clone(),
+        )
+    });
+    let classes = finalize_samples(
+        &args,
+        &parser,
+        spill.finish(),
+        decontaminator.as_mut(),
+        &mut filter_stats,
+        &mut report,
+    );
+    report
+        .timings
+        .insert("finalize", t0.elapsed().as_secs_f32());
+
+    filter_stats.print_summary();
+    filter_stats.persist_samples(&args.output_dir);
+    report.filter_reasons = filter_stats.counts.clone();
+
+    if decontaminator.is_some() || !deny_list.is_empty() {
+        let stats = match decontaminator {
+            Some(d) => d.stats(deny_list.counts),
+            None => DecontaminationStats {
+                excluded_slices: deny_list.counts,
+                ..Default::default()
+            },
+        };
+        for (pattern, n) in &stats.excluded_slices {
+            println!("[i] Skipped {} slice files matching '{}'", n, pattern);
+        }
+        for (source, counts) in &stats.sources {
+            println!(
+                "[i] Removed {} exact and {} near matches of '{}'",
+                counts.exact, counts.near, source
+            );
+        }
+        report.decontamination = Some(stats);
+    }
+
+    if args.hierarchical_labels {
+        utils::persist_hierarchy(&args.output_dir, classes.iter());
+    }
 
-    let imported_slices = import_slices(&args);
-    vectorize_slices(&args, imported_slices);
+    report.persist(&args.output_dir);
 }
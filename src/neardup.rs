@@ -0,0 +1,322 @@
+use crate::slice_structs::{FeatureValues, Sample};
+use crate::utils;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+const NUM_PERM: usize = 128;
+const NUM_LARGEST_CLUSTERS: usize = 10;
+
+/// Identifies a deduplicated sample within one run
+pub fn sample_key(sample: &Sample) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    sample.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn hash_one<T: Hash>(value: T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+fn usage_tokens<S: AsRef<str>>(calls: &[S], arg_tos: &[S], language: Option<&str>) -> Vec<u64> {
+    let clean = |c: &S| utils::clean_feature(c.as_ref(), language);
+    calls
+        .iter()
+        .map(|c| hash_one(("call", clean(c))))
+        .chain(arg_tos.iter().map(|c| hash_one(("arg", clean(c)))))
+        .collect()
+}
+
+fn sorted_unique(mut tokens: Vec<u64>) -> Vec<u64> {
+    tokens.sort_unstable();
+    tokens.dedup();
+    tokens
+}
+
+/// Sorted, unique token hashes of the calls and argument-to calls of a sample. Variable and scope
+/// are ignored, so copies that only differ in naming end up with the same tokens.
+fn call_tokens(values: &FeatureValues, language: Option<&str>) -> Vec<u64> {
+    sorted_unique(usage_tokens(&values.calls, &values.arg_tos, language))
+}
+
+/// Sorted, unique token hashes of all parts of a feature: variable, scope and usages. Values are
+/// cleaned like the rendered feature, so they match the tokens of `feature_tokens`.
+pub fn value_tokens(values: &FeatureValues, language: Option<&str>) -> Vec<u64> {
+    let mut tokens = usage_tokens(&values.calls, &values.arg_tos, language);
+    tokens.push(hash_one((
+        "var",
+        utils::clean_feature(&values.variable, language),
+    )));
+    tokens.push(hash_one((
+        "scope",
+        utils::clean_feature(&values.scope, language),
+    )));
+    sorted_unique(tokens)
+}
+
+/// Token hashes of a feature string in the default layout, as read from held-out datasets
+pub fn feature_tokens(feature: &str) -> Vec<u64> {
+    let mut calls = Vec::new();
+    let mut arg_tos = Vec::new();
+    let mut tokens = Vec::new();
+    for section in feature.split(" ;") {
+        let section = section.trim_start();
+        if let Some(names) = section.strip_prefix("Calls: ") {
+            calls.extend(names.split(", "));
+        } else if let Some(names) = section.strip_prefix("Argument to: ") {
+            arg_tos.extend(names.split(", "));
+        } else if let Some(name) = section.strip_prefix("Variable: ") {
+            tokens.push(hash_one(("var", name)));
+        } else if let Some(name) = section.strip_prefix("Scope: ") {
+            tokens.push(hash_one(("scope", name)));
+        }
+    }
+    tokens.extend(usage_tokens(&calls, &arg_tos, None));
+    sorted_unique(tokens)
+}
+
+fn jaccard(a: &[u64], b: &[u64]) -> f64 {
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                shared += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    shared as f64 / (a.len() + b.len() - shared) as f64
+}
+
+/// Largest near-duplicate clusters, described by their first sample
+#[derive(Debug, Serialize)]
+pub struct Cluster {
+    pub size: usize,
+    pub label: String,
+    pub feature: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct NearDupStats {
+    pub threshold: f64,
+    pub removed: usize,
+    /// Number of clusters per cluster size, singletons included
+    pub cluster_sizes: BTreeMap<usize, usize>,
+    pub largest_clusters: Vec<Cluster>,
+}
+
+/// MinHash/LSH index over token sets. Candidates from the LSH buckets are verified with their
+/// exact Jaccard similarity, so the threshold is respected exactly. Entries are only compared to
+/// entries inserted with the same `salt`.
+pub struct MinHashIndex {
+    threshold: f64,
+    rows: usize,
+    seeds: Vec<u64>,
+    /// Entries per band key, all of them are candidates for a query sharing the key
+    buckets: HashMap<u64, Vec<u32>>,
+    entries: Vec<Vec<u64>>,
+}
+
+impl MinHashIndex {
+    pub fn new(threshold: f64) -> Self {
+        assert!(
+            threshold > 0.0 && threshold <= 1.0,
+            "Similarity threshold has to be in (0, 1]"
+        );
+
+        // pick the band layout with the largest LSH threshold `(1/b)^(1/r)` not above the
+        // similarity threshold, so candidate generation favors recall
+        let rows = (0..=NUM_PERM.trailing_zeros())
+            .map(|i| 1 << i)
+            .filter(|r| (1.0 / (NUM_PERM / r) as f64).powf(1.0 / *r as f64) <= threshold)
+            .max()
+            .unwrap_or(1);
+
+        MinHashIndex {
+            threshold,
+            rows,
+            seeds: (0..NUM_PERM as u64).map(splitmix64).collect(),
+            buckets: HashMap::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// LSH band keys of a non-empty token set
+    pub fn band_keys(&self, tokens: &[u64], salt: &str) -> Vec<u64> {
+        let signature: Vec<u64> = self
+            .seeds
+            .iter()
+            .map(|seed| tokens.iter().map(|t| splitmix64(t ^ seed)).min().unwrap())
+            .collect();
+        signature
+            .chunks(self.rows)
+            .enumerate()
+            .map(|(i, band)| hash_one((salt, i, band)))
+            .collect()
+    }
+
+    /// Most similar entry reaching the threshold
+    pub fn find(&self, tokens: &[u64], band_keys: &[u64]) -> Option<u32> {
+        let mut seen = HashSet::new();
+        band_keys
+            .iter()
+            .filter_map(|k| self.buckets.get(k))
+            .flatten()
+            .filter(|e| seen.insert(**e))
+            .map(|e| (*e, jaccard(tokens, &self.entries[*e as usize])))
+            .filter(|(_, sim)| *sim >= self.threshold)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(e, _)| e)
+    }
+
+    pub fn insert(&mut self, tokens: Vec<u64>, band_keys: Vec<u64>) -> u32 {
+        let entry = self.entries.len() as u32;
+        self.entries.push(tokens);
+        for key in band_keys {
+            self.buckets.entry(key).or_default().push(entry);
+        }
+        entry
+    }
+}
+
+struct Representative {
+    key: u64,
+    size: usize,
+}
+
+/// Near-duplicate detection over the call tokens of samples with the same label
+pub struct NearDupDetector {
+    index: MinHashIndex,
+    language: Option<String>,
+    reps: Vec<Representative>,
+    /// Keys of the samples that are near duplicates of an earlier sample
+    pub duplicates: HashSet<u64>,
+}
+
+impl NearDupDetector {
+    pub fn new(threshold: f64, language: Option<String>) -> Self {
+        NearDupDetector {
+            index: MinHashIndex::new(threshold),
+            language,
+            reps: Vec::new(),
+            duplicates: HashSet::new(),
+        }
+    }
+
+    /// Checks a sample against all previously seen samples, returns whether it is a near duplicate
+    pub fn check(&mut self, sample: &Sample) -> bool {
+        let tokens = call_tokens(&sample.values, self.language.as_deref());
+        if tokens.is_empty() {
+            return false;
+        }
+
+        let band_keys = self.index.band_keys(&tokens, &sample.label);
+        if let Some(rep) = self.index.find(&tokens, &band_keys) {
+            self.reps[rep as usize].size += 1;
+            self.duplicates.insert(sample_key(sample));
+            return true;
+        }
+
+        self.index.insert(tokens, band_keys);
+        self.reps.push(Representative {
+            key: sample_key(sample),
+            size: 1,
+        });
+        false
+    }
+
+    /// Cluster statistics and keys of the representatives of the largest clusters
+    pub fn stats(&self) -> (NearDupStats, HashMap<u64, usize>) {
+        let mut cluster_sizes = BTreeMap::new();
+        for rep in &self.reps {
+            *cluster_sizes.entry(rep.size).or_insert(0) += 1;
+        }
+
+        let mut largest: Vec<&Representative> = self.reps.iter().filter(|r| r.size > 1).collect();
+        largest.sort_by_key(|r| std::cmp::Reverse(r.size));
+        largest.truncate(NUM_LARGEST_CLUSTERS);
+
+        let stats = NearDupStats {
+            threshold: self.index.threshold,
+            removed: self.duplicates.len(),
+            cluster_sizes,
+            largest_clusters: Vec::new(),
+        };
+        (stats, largest.iter().map(|r| (r.key, r.size)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_entry_of_a_bucket_is_a_candidate() {
+        let mut index = MinHashIndex::new(0.8);
+        let first: Vec<u64> = (0..10).collect();
+        let second: Vec<u64> = (100..110).collect();
+        let keys = index.band_keys(&first, "string");
+
+        // force both entries into the same buckets
+        index.insert(first.clone(), keys.clone());
+        index.insert(second.clone(), keys.clone());
+
+        assert_eq!(index.find(&first, &keys), Some(0));
+        assert_eq!(index.find(&second, &keys), Some(1));
+        assert_eq!(index.find(&[200, 201], &keys), None);
+    }
+
+    #[test]
+    fn most_similar_entry_is_found() {
+        let mut index = MinHashIndex::new(0.5);
+        let base: Vec<u64> = (0..20).collect();
+        let far: Vec<u64> = (0..12).chain(50..58).collect();
+        let close: Vec<u64> = (0..19).chain(60..61).collect();
+        for tokens in [far, close] {
+            let keys = index.band_keys(&tokens, "string");
+            index.insert(tokens, keys);
+        }
+
+        assert_eq!(
+            index.find(&base, &index.band_keys(&base, "string")),
+            Some(1)
+        );
+        assert_eq!(index.find(&base, &index.band_keys(&base, "number")), None);
+    }
+
+    #[test]
+    fn value_tokens_match_rendered_feature() {
+        let values = FeatureValues {
+            variable: "user".to_string(),
+            scope: "app.py::<module>.load".to_string(),
+            calls: vec!["<module>.fetch".to_string(), "json.loads".to_string()],
+            arg_tos: vec!["print".to_string()],
+            ..Default::default()
+        };
+        let language = Some("python".to_string());
+        let feature = utils::render(
+            &crate::template::FeatureTemplate::default(),
+            &values,
+            &language,
+            utils::CallOrder::Set,
+        );
+
+        assert_eq!(
+            value_tokens(&values, language.as_deref()),
+            feature_tokens(&feature)
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use crate::slice_structs::JsonObjSlice;
+use crate::utils::{self, Parser};
+use std::collections::HashSet;
+
+/// Names of the calls an object is used in, without operators that nearly every object shares
+fn call_names<'a>(obj: &'a JsonObjSlice) -> HashSet<&'a str> {
+    obj.invoked_calls
+        .iter()
+        .chain(obj.arg_to_calls.iter().map(|(c, _)| c))
+        .map(|c| &*c.call_name)
+        .filter(|c| !c.starts_with("<operator>"))
+        .collect()
+}
+
+/// Name of a sibling as it appears in the features, `None` for literals and temporaries
+fn sibling_name<'a>(parser: &Parser, obj: &'a JsonObjSlice) -> Option<&'a str> {
+    let name: &str = &obj.target_obj.name;
+    let name = match parser.finder_colon.find(name.as_bytes()) {
+        Some(i) => &name[..i],
+        None => name,
+    };
+    if obj.target_obj.literal || name.is_empty() || name.starts_with("_tmp_") {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Label of the known type of an object, as the object would be labeled itself
+fn type_label(parser: &Parser, obj: &JsonObjSlice) -> Option<String> {
+    let type_name: &str = &obj.target_obj.type_full_name;
+    if type_name.is_empty() || type_name == "ANY" {
+        None
+    } else {
+        let type_name = utils::type_label(parser, type_name);
+        Some(utils::merge_synonyms(parser, &type_name))
+    }
+}
+
+/// Selects up to `k` co-occurring variables for every object of a scope. Siblings are ranked by
+/// the number of calls they share with the object, ties are broken by name. With `types`, known
+/// types are appended as `name: type`, they are only available when building training data.
+/// The selection does not depend on the types, neither of the siblings nor of the object.
+pub fn select(parser: &Parser, vars: &[JsonObjSlice], k: usize, types: bool) -> Vec<Vec<String>> {
+    let calls: Vec<HashSet<&str>> = vars.iter().map(call_names).collect();
+    let names: Vec<Option<&str>> = vars.iter().map(|v| sibling_name(parser, v)).collect();
+    let labels: Vec<Option<String>> = if types {
+        vars.iter().map(|v| type_label(parser, v)).collect()
+    } else {
+        vec![None; vars.len()]
+    };
+
+    (0..vars.len())
+        .map(|i| {
+            let mut ranked: Vec<(usize, &str, usize)> = (0..vars.len())
+                .filter(|j| *j != i && names[*j].is_some() && names[*j] != names[i])
+                .map(|j| {
+                    (
+                        calls[i].intersection(&calls[j]).count(),
+                        names[j].unwrap(),
+                        j,
+                    )
+                })
+                .filter(|(shared, _, _)| *shared > 0)
+                .collect();
+            ranked.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+
+            let mut seen = HashSet::new();
+            ranked
+                .into_iter()
+                .filter(|(_, name, _)| seen.insert(*name))
+                .take(k)
+                .map(|(_, name, j)| match &labels[j] {
+                    Some(label) => format!("{}: {}", name, label),
+                    None => name.to_owned(),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str, type_name: &str, calls: &[&str]) -> String {
+        let calls: Vec<String> = calls
+            .iter()
+            .map(|c| {
+                format!(
+                    r#"{{"callName": "{}", "paramTypes": [], "returnType": "ANY"}}"#,
+                    c
+                )
+            })
+            .collect();
+        format!(
+            r#"{{"targetObj": {{"name": "{}", "typeFullName": "{}", "literal": false}},
+                "invokedCalls": [{}], "argToCalls": []}}"#,
+            name,
+            type_name,
+            calls.join(", ")
+        )
+    }
+
+    fn scope(vars: &[String]) -> String {
+        format!("[{}]", vars.join(", "))
+    }
+
+    #[test]
+    fn siblings_are_ranked_by_shared_calls() {
+        let parser = Parser::new(&Some("typescript".to_string()));
+        let source = scope(&[
+            var("req", "Request", &["get", "send", "end"]),
+            var("res", "Response", &["send", "end"]),
+            var("app", "Express", &["get"]),
+            var("log", "Logger", &["info"]),
+        ]);
+        let vars: Vec<JsonObjSlice> = serde_json::from_str(&source).unwrap();
+
+        let siblings = select(&parser, &vars, 2, false);
+        assert_eq!(siblings[0], vec!["res", "app"]);
+        assert_eq!(siblings[1], vec!["req"]);
+        assert!(siblings[3].is_empty());
+    }
+
+    #[test]
+    fn sibling_types_do_not_change_the_selection() {
+        let parser = Parser::new(&Some("typescript".to_string()));
+        let source = scope(&[
+            var("a", "__ecma.String", &["trim", "split"]),
+            var("b", "__ecma.String", &["trim", "split"]),
+            var("c", "Buffer", &["split"]),
+            var("d", "ANY", &["trim"]),
+        ]);
+        let vars: Vec<JsonObjSlice> = serde_json::from_str(&source).unwrap();
+
+        // siblings of the same type as the object are kept
+        let siblings = select(&parser, &vars, 3, true);
+        assert_eq!(siblings[0], vec!["b: string", "c: Buffer", "d"]);
+        assert_eq!(siblings[3], vec!["a: string", "b: string"]);
+
+        assert_eq!(select(&parser, &vars, 3, false)[0], vec!["b", "c", "d"]);
+    }
+}
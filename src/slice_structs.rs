@@ -1,48 +1,352 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
 
-use serde::Deserialize;
-use serde_json;
+use crate::augment::AugmentMethod;
+use crate::utils::{self, CallOrder, SplitStrategy};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+
+/// String borrowed from the input buffer, only owned if the JSON string contains escape sequences
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct Str<'a>(#[serde(borrow)] pub Cow<'a, str>);
+
+impl Deref for Str<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Str<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
 
 /// Root Node for Slice Deserialization
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
-pub struct FullSlice {
-    pub object_slices: HashMap<String, Vec<JsonObjSlice>>,
-    pub user_defined_types: Vec<serde_json::Value>,
+pub struct FullSlice<'a> {
+    #[serde(borrow)]
+    pub object_slices: HashMap<Str<'a>, Vec<JsonObjSlice<'a>>>,
+    #[serde(borrow)]
+    pub user_defined_types: Vec<UserDefinedType<'a>>,
 }
 
+/// Slice file reduced to the names it defines, objects are skipped
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
-pub struct JsonObjSlice {
-    pub target_obj: TargetObj,
-    pub defined_by: serde_json::Value,
-    pub invoked_calls: Vec<Call>,
-    pub arg_to_calls: Vec<(Call, i32)>,
+pub struct NamesSlice<'a> {
+    #[serde(borrow)]
+    pub object_slices: HashMap<Str<'a>, IgnoredAny>,
+    #[serde(borrow)]
+    pub user_defined_types: Vec<UserDefinedType<'a>>,
 }
 
+/// Type declared in the sliced program
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
-pub struct TargetObj {
-    pub name: String,
-    pub type_full_name: String,
+pub struct UserDefinedType<'a> {
+    #[serde(borrow)]
+    pub name: Str<'a>,
+    #[serde(default, borrow)]
+    pub procedures: Vec<Procedure<'a>>,
+}
+
+/// Method declared by a user-defined type
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Procedure<'a> {
+    #[serde(borrow)]
+    pub call_name: Str<'a>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonObjSlice<'a> {
+    #[serde(borrow)]
+    pub target_obj: TargetObj<'a>,
+    #[serde(borrow)]
+    pub defined_by: Option<DefComponent<'a>>,
+    #[serde(borrow)]
+    pub invoked_calls: Vec<Call<'a>>,
+    #[serde(borrow)]
+    pub arg_to_calls: Vec<(Call<'a>, i32)>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetObj<'a> {
+    #[serde(borrow)]
+    pub name: Str<'a>,
+    #[serde(borrow)]
+    pub type_full_name: Str<'a>,
     pub literal: bool,
 }
 
+/// Expression defining an object, only its code is kept
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DefComponent<'a> {
+    #[serde(borrow)]
+    pub name: Str<'a>,
+}
+
 #[derive(Clone, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
-pub struct Call {
-    pub receiver: Option<String>,
-    pub call_name: String,
-    pub param_types: Vec<serde_json::Value>,
-    pub return_type: String,
+pub struct Call<'a> {
+    #[serde(borrow)]
+    pub receiver: Option<Str<'a>>,
+    #[serde(borrow)]
+    pub call_name: Str<'a>,
+    pub param_types: IgnoredAny,
+    #[serde(borrow)]
+    pub return_type: Str<'a>,
+}
+
+/// Deduplicates strings that repeat across the corpus, such as call, type and scope names
+#[derive(Default)]
+pub struct Interner {
+    strings: HashSet<Rc<str>>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, s: &str) -> Rc<str> {
+        match self.strings.get(s) {
+            Some(interned) => interned.clone(),
+            None => {
+                let interned: Rc<str> = Rc::from(s);
+                self.strings.insert(interned.clone());
+                interned
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
 }
 
 /// Data Structure used for internal representation of Slice
 #[derive(Debug)]
 pub struct ObjSlice {
     pub name: String,
+    pub scope: Rc<str>,
+    pub type_name: Rc<str>,
+    pub invoked_calls: Vec<Rc<str>>,
+    pub arg_to_calls: Vec<(Rc<str>, i32)>,
+    /// Return types of the invoked calls
+    pub return_types: Vec<Rc<str>>,
+    pub defined_by: Option<String>,
+    pub literal: bool,
+    /// Kind of the literal the object is or is defined by
+    pub literal_kind: Option<&'static str>,
+    /// Co-occurring variables of the same scope, with `--siblings`
+    pub siblings: Vec<String>,
+}
+
+impl ObjSlice {
+    /// Copies the fields needed after import out of the borrowed input buffer
+    pub fn new(
+        obj: &JsonObjSlice,
+        scope: Rc<str>,
+        type_name: &str,
+        interner: &mut Interner,
+    ) -> Self {
+        ObjSlice {
+            name: obj.target_obj.name.to_string(),
+            scope,
+            type_name: interner.intern(type_name),
+            invoked_calls: obj
+                .invoked_calls
+                .iter()
+                .map(|c| interner.intern(&c.call_name))
+                .collect(),
+            arg_to_calls: obj
+                .arg_to_calls
+                .iter()
+                .map(|(c, i)| (interner.intern(&c.call_name), *i))
+                .collect(),
+            return_types: obj
+                .invoked_calls
+                .iter()
+                .map(|c| interner.intern(&c.return_type))
+                .collect(),
+            defined_by: obj.defined_by.as_ref().map(|d| d.name.to_string()),
+            literal: obj.target_obj.literal,
+            literal_kind: utils::literal_kind(&obj.target_obj.name)
+                .or_else(|| {
+                    obj.defined_by
+                        .as_ref()
+                        .and_then(|d| utils::literal_kind(&d.name))
+                })
+                .or(obj.target_obj.literal.then_some("other")),
+            siblings: Vec::new(),
+        }
+    }
+}
+
+/// Call the object is passed to and the position of the argument
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ArgTo {
+    pub call: String,
+    pub position: i32,
+}
+
+/// Structured counterpart of a feature string
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FeatureRecord {
+    pub variable: String,
+    /// Scope split into file and enclosing functions
+    pub scope: Vec<String>,
+    /// Invoked calls in order of their first invocation
+    pub calls: Vec<String>,
+    pub arg_tos: Vec<ArgTo>,
+    /// Expression the object is defined by, if known
+    pub defined_by: Option<String>,
+    pub literal: bool,
+    pub literal_kind: Option<String>,
+    /// Co-occurring variables of the same scope, with `--siblings`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub siblings: Vec<String>,
+    /// Sub-tokens of the identifiers, with `--subtokens field`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtokens: Option<RecordSubtokens>,
+}
+
+/// Sub-tokens of the identifiers of a record, calls and argument-to entries in record order
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecordSubtokens {
+    pub variable: Vec<String>,
+    pub scope: Vec<String>,
+    pub calls: Vec<Vec<String>>,
+    pub arg_tos: Vec<Vec<String>>,
+}
+
+impl FeatureRecord {
+    /// Record of a subset or reordering of the usages, argument positions are kept. Positions are
+    /// looked up by plain call name, entries of sequences may carry their repetitions.
+    pub fn with_usages(&self, calls: &[String], arg_tos: &[String], call_order: CallOrder) -> Self {
+        let mut positions: HashMap<&str, Vec<i32>> = HashMap::new();
+        for arg_to in &self.arg_tos {
+            let merged = positions
+                .entry(utils::split_count(&arg_to.call, call_order).0)
+                .or_default();
+            if !merged.contains(&arg_to.position) {
+                merged.push(arg_to.position);
+            }
+        }
+
+        FeatureRecord {
+            calls: calls.to_vec(),
+            arg_tos: arg_tos
+                .iter()
+                .flat_map(|call| {
+                    positions
+                        .get(utils::split_count(call, call_order).0)
+                        .into_iter()
+                        .flatten()
+                        .map(|position| ArgTo {
+                            call: call.to_owned(),
+                            position: *position,
+                        })
+                })
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Adds the sub-tokens of the identifiers as a separate field
+    pub fn with_subtokens(self, call_order: CallOrder) -> Self {
+        let subtokens = RecordSubtokens {
+            variable: utils::subtokens(&self.variable),
+            scope: self
+                .scope
+                .iter()
+                .flat_map(|c| utils::subtokens(c))
+                .collect(),
+            calls: self
+                .calls
+                .iter()
+                .map(|c| utils::subtokens(utils::split_count(c, call_order).0))
+                .collect(),
+            arg_tos: self
+                .arg_tos
+                .iter()
+                .map(|a| utils::subtokens(utils::split_count(&a.call, call_order).0))
+                .collect(),
+        };
+        FeatureRecord {
+            subtokens: Some(subtokens),
+            ..self
+        }
+    }
+}
+
+/// Values a feature is rendered from. They are kept with the sample, so that later stages work on
+/// the usages instead of parsing the feature and render the feature again after changing them.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct FeatureValues {
+    /// Variable name as it appears in the feature
+    pub variable: String,
+    pub scope: String,
+    pub calls: Vec<String>,
+    pub arg_tos: Vec<String>,
+    pub return_types: Vec<String>,
+    pub literal_kind: Option<String>,
+    pub siblings: Vec<String>,
+}
+
+/// Vectorized sample, as spilled to disk and written to the dataset
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Sample {
+    pub feature: String,
+    pub values: FeatureValues,
+    pub label: String,
+    pub num_usages: usize,
+    /// Provenance, not considered when comparing samples
+    pub project: String,
+    /// Slice file of the object, `<archive>!/<member>` for members of archives
+    pub source: String,
     pub scope: String,
-    pub type_name: String,
-    pub invoked_calls: Vec<Call>,
-    pub arg_to_calls: Vec<(Call, i32)>,
+    pub variable: String,
+    /// Strategy that split the usages of the object, `None` if they fit into a single sample
+    pub split_strategy: Option<SplitStrategy>,
+    /// Index of the chunk among the samples generated from the same object
+    pub chunk: usize,
+    /// Transformation that generated the sample, `None` for samples taken from the slices
+    pub augmentation: Option<AugmentMethod>,
+    /// Whether the object is a literal
+    pub literal: bool,
+    /// Structured features, only kept if records are written
+    pub record: Option<FeatureRecord>,
+}
+
+/// Samples are duplicates if feature, label and usage count match, regardless of their provenance
+impl PartialEq for Sample {
+    fn eq(&self, other: &Self) -> bool {
+        self.feature == other.feature
+            && self.label == other.label
+            && self.num_usages == other.num_usages
+    }
+}
+
+impl Eq for Sample {}
+
+impl Hash for Sample {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.feature.hash(state);
+        self.label.hash(state);
+        self.num_usages.hash(state);
+    }
 }
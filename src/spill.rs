@@ -0,0 +1,233 @@
+use crate::slice_structs::Sample;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Spills samples into hash partitions on disk, so that deduplication only ever needs one partition in memory
+pub struct SpillWriter {
+    dir: PathBuf,
+    partitions: Vec<BufWriter<File>>,
+    max_partition_bytes: u64,
+    num_samples: usize,
+}
+
+/// Partition of a sample among `n`. Samples are partitioned by feature and usage count only, so
+/// that samples which become duplicates after relabeling still end up in the same partition.
+/// Oversized partitions are split again with a different `salt`.
+fn partition_index(sample: &Sample, salt: u64, n: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    if salt > 0 {
+        salt.hash(&mut hasher);
+    }
+    sample.feature.hash(&mut hasher);
+    sample.num_usages.hash(&mut hasher);
+    (hasher.finish() % n as u64) as usize
+}
+
+fn parse_sample(line: std::io::Result<String>) -> Sample {
+    let line = line.expect("Failed to read spill partition");
+    serde_json::from_str(&line).expect("Failed to parse spilled sample")
+}
+
+/// Splits a partition larger than `max_bytes` into smaller ones, until they fit or only hold
+/// samples with the same feature and usage count
+fn split_oversized(path: PathBuf, max_bytes: u64, salt: u64) -> Vec<PathBuf> {
+    let size = fs::metadata(&path)
+        .expect("Failed to read spill partition")
+        .len();
+    if size <= max_bytes {
+        return vec![path];
+    }
+
+    let n = size.div_ceil(max_bytes) as usize + 1;
+    let stem = path.file_stem().unwrap().to_string_lossy().into_owned();
+    let sub_paths: Vec<PathBuf> = (0..n)
+        .map(|j| path.with_file_name(format!("{}-{}.jsonl", stem, j)))
+        .collect();
+    let mut writers: Vec<BufWriter<File>> = sub_paths
+        .iter()
+        .map(|p| BufWriter::new(File::create(p).expect("Failed to create spill partition")))
+        .collect();
+
+    let reader = BufReader::new(File::open(&path).expect("Failed to open spill partition"));
+    for line in reader.lines() {
+        let line = line.expect("Failed to read spill partition");
+        let sample: Sample = serde_json::from_str(&line).expect("Failed to parse spilled sample");
+        let writer = &mut writers[partition_index(&sample, salt, n)];
+        writer
+            .write_all(line.as_bytes())
+            .expect("Failed to spill sample");
+        writer.write_all(b"\n").expect("Failed to spill sample");
+    }
+    for mut writer in writers {
+        writer.flush().expect("Failed to flush spill partition");
+    }
+    fs::remove_file(&path).expect("Failed to remove spill partition");
+
+    sub_paths
+        .into_iter()
+        .flat_map(|p| {
+            let sub_size = fs::metadata(&p).map_or(0, |m| m.len());
+            if sub_size == 0 {
+                let _ = fs::remove_file(&p);
+                Vec::new()
+            } else if sub_size < size {
+                split_oversized(p, max_bytes, salt + 1)
+            } else {
+                // a single feature, splitting again would not help
+                vec![p]
+            }
+        })
+        .collect()
+}
+
+impl SpillWriter {
+    pub fn create(dir: &Path, num_partitions: usize, max_partition_bytes: u64) -> Self {
+        fs::create_dir_all(dir).expect("Failed to create spill directory");
+
+        let partitions = (0..num_partitions.max(1))
+            .map(|i| {
+                let file = File::create(dir.join(format!("part-{:05}.jsonl", i)))
+                    .expect("Failed to create spill partition");
+                BufWriter::new(file)
+            })
+            .collect();
+
+        SpillWriter {
+            dir: dir.to_path_buf(),
+            partitions,
+            max_partition_bytes: max_partition_bytes.max(1),
+            num_samples: 0,
+        }
+    }
+
+    pub fn push(&mut self, sample: &Sample) {
+        let i = partition_index(sample, 0, self.partitions.len());
+        let partition = &mut self.partitions[i];
+        serde_json::to_writer(&mut *partition, sample).expect("Failed to spill sample");
+        partition.write_all(b"\n").expect("Failed to spill sample");
+        self.num_samples += 1;
+    }
+
+    /// Flushes the partitions and splits those larger than the partition budget
+    pub fn finish(self) -> Spill {
+        let num_partitions = self.partitions.len();
+        for mut partition in self.partitions {
+            partition.flush().expect("Failed to flush spill partition");
+        }
+
+        let paths = (0..num_partitions)
+            .flat_map(|i| {
+                let path = self.dir.join(format!("part-{:05}.jsonl", i));
+                split_oversized(path, self.max_partition_bytes, 1)
+            })
+            .collect();
+
+        Spill {
+            paths,
+            dir: self.dir,
+            num_samples: self.num_samples,
+        }
+    }
+}
+
+/// Read-only view on spilled samples, the spill directory is removed once it is dropped
+pub struct Spill {
+    dir: PathBuf,
+    paths: Vec<PathBuf>,
+    pub num_samples: usize,
+}
+
+impl Spill {
+    pub fn num_partitions(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// Iterates over the partitions, the samples of a partition are read as they are consumed
+    pub fn partitions(&self) -> impl Iterator<Item = impl Iterator<Item = Sample>> + '_ {
+        self.paths.iter().map(|path| {
+            let reader = BufReader::new(File::open(path).expect("Failed to open spill partition"));
+            reader.lines().map(parse_sample)
+        })
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slice_structs::FeatureValues;
+
+    fn sample(variable: &str, label: &str) -> Sample {
+        Sample {
+            feature: format!("Variable: {} ; Scope: main ;", variable),
+            values: FeatureValues {
+                variable: variable.to_string(),
+                scope: "main".to_string(),
+                ..Default::default()
+            },
+            label: label.to_string(),
+            num_usages: 1,
+            project: "projA".to_string(),
+            source: "projA/slices.json".to_string(),
+            scope: "main".to_string(),
+            variable: variable.to_string(),
+            split_strategy: None,
+            chunk: 0,
+            augmentation: None,
+            literal: false,
+            record: None,
+        }
+    }
+
+    fn spill_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("extys-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn oversized_partitions_are_split() {
+        let mut writer = SpillWriter::create(&spill_dir("spill-split"), 1, 4096);
+        for i in 0..200 {
+            writer.push(&sample(&format!("v{}", i), "string"));
+            // relabeled duplicates have to stay in the partition of the original
+            writer.push(&sample(&format!("v{}", i), "number"));
+        }
+        let spill = writer.finish();
+
+        assert!(spill.num_partitions() > 1);
+        let mut num_samples = 0;
+        for partition in spill.partitions() {
+            let partition: Vec<Sample> = partition.collect();
+            num_samples += partition.len();
+            for s in &partition {
+                assert_eq!(
+                    partition.iter().filter(|o| o.feature == s.feature).count(),
+                    2
+                );
+            }
+        }
+        assert_eq!(num_samples, 400);
+        for path in &spill.paths {
+            assert!(fs::metadata(path).unwrap().len() <= 4096);
+        }
+    }
+
+    #[test]
+    fn partitions_of_one_feature_are_kept() {
+        let mut writer = SpillWriter::create(&spill_dir("spill-single"), 1, 1024);
+        for _ in 0..100 {
+            writer.push(&sample("v", "string"));
+        }
+        let spill = writer.finish();
+
+        let sizes: Vec<usize> = spill.partitions().map(|p| p.count()).collect();
+        assert_eq!(sizes, vec![100]);
+    }
+}
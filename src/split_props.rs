@@ -0,0 +1,88 @@
+use crate::utils::{split_usages, SplitConfig, SplitStrategy};
+use proptest::prelude::*;
+use std::collections::HashSet;
+
+fn usages(prefix: &'static str, max_len: usize) -> impl Strategy<Value = Vec<String>> {
+    (0..max_len).prop_map(move |n| (0..n).map(|i| format!("{}{}", prefix, i)).collect())
+}
+
+fn split_config() -> impl Strategy<Value = SplitConfig> {
+    (
+        prop_oneof![
+            Just(SplitStrategy::Contiguous),
+            Just(SplitStrategy::Sliding),
+            Just(SplitStrategy::Random),
+        ],
+        1usize..12,
+        0usize..12,
+        any::<u64>(),
+    )
+        .prop_map(|(strategy, threshold, overlap, seed)| SplitConfig {
+            strategy,
+            threshold,
+            overlap,
+            seed,
+        })
+}
+
+proptest! {
+    #[test]
+    fn every_usage_is_in_some_chunk(
+        a in usages("call", 40),
+        b in usages("arg", 40),
+        config in split_config(),
+    ) {
+        let chunks = split_usages(a.clone(), b.clone(), &config);
+
+        let seen_a: HashSet<&String> = chunks.iter().flat_map(|c| &c.0).collect();
+        let seen_b: HashSet<&String> = chunks.iter().flat_map(|c| &c.1).collect();
+        for usage in &a {
+            prop_assert!(seen_a.contains(usage), "{} missing in {:?}", usage, chunks);
+        }
+        for usage in &b {
+            prop_assert!(seen_b.contains(usage), "{} missing in {:?}", usage, chunks);
+        }
+    }
+
+    #[test]
+    fn chunks_respect_the_upper_bound(
+        a in usages("call", 40),
+        b in usages("arg", 40),
+        config in split_config(),
+    ) {
+        // contiguous chunks repeat shared usages and may exceed the bound
+        prop_assume!(config.strategy != SplitStrategy::Contiguous);
+        prop_assume!(!a.is_empty() || !b.is_empty());
+        for chunk in split_usages(a, b, &config) {
+            prop_assert!(!chunk.0.is_empty() || !chunk.1.is_empty());
+            prop_assert!(chunk.0.len() + chunk.1.len() <= config.threshold);
+        }
+    }
+
+    #[test]
+    fn splits_are_deterministic(
+        a in usages("call", 40),
+        b in usages("arg", 40),
+        config in split_config(),
+    ) {
+        prop_assert_eq!(
+            split_usages(a.clone(), b.clone(), &config),
+            split_usages(a, b, &config)
+        );
+    }
+
+    #[test]
+    fn truncate_keeps_one_chunk_of_original_usages(
+        a in usages("call", 40),
+        b in usages("arg", 40),
+        threshold in 1usize..12,
+    ) {
+        let config = SplitConfig { strategy: SplitStrategy::Truncate, threshold, overlap: 0, seed: 0 };
+        let chunks = split_usages(a.clone(), b.clone(), &config);
+
+        prop_assert_eq!(chunks.len(), 1);
+        let (chunk_a, chunk_b) = &chunks[0];
+        prop_assert_eq!(chunk_a.len() + chunk_b.len(), (a.len() + b.len()).min(threshold));
+        prop_assert!(a.starts_with(chunk_a) && b.starts_with(chunk_b));
+    }
+}
@@ -0,0 +1,191 @@
+use crate::augment::AugmentStats;
+use crate::decontam::DecontaminationStats;
+use crate::neardup::NearDupStats;
+use crate::vocab::VocabChanges;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+/// Numbers gathered while importing the slice files
+#[derive(Debug, Default, Serialize)]
+pub struct ImportStats {
+    pub num_files: usize,
+    pub num_scopes: usize,
+    pub num_objects: usize,
+    pub num_candidates: usize,
+    /// Number of slice inputs whose results were taken from the import cache
+    pub cached_inputs: usize,
+    pub scopes_per_file: f32,
+    pub candidates_per_file: f32,
+    pub objects_per_file: f32,
+    pub candidate_percentage: f32,
+}
+
+/// Numbers describing the final dataset
+#[derive(Debug, Default, Serialize)]
+pub struct DatasetStats {
+    pub num_samples: usize,
+    pub num_classes: usize,
+    pub avg_occurrences: f32,
+    pub median_occurrences: usize,
+}
+
+/// Machine-readable summary of a run, persisted as `stats.json`
+#[derive(Debug, Default, Serialize)]
+pub struct RunReport {
+    pub version: &'static str,
+    pub config: serde_json::Value,
+    pub import: ImportStats,
+    pub dataset: DatasetStats,
+    pub filter_reasons: BTreeMap<FilterReason, usize>,
+    /// Number of samples per train/val/test split, empty if no splits were requested
+    pub split_sizes: BTreeMap<&'static str, usize>,
+    /// Near-duplicate clusters, if near-duplicate removal is enabled
+    pub near_duplicates: Option<NearDupStats>,
+    /// Held-out matches per source and skipped slice files per deny-list pattern
+    pub decontamination: Option<DecontaminationStats>,
+    /// Changes relative to the input label vocabulary, if one was given
+    pub vocab_changes: Option<VocabChanges>,
+    /// Samples per class that come from literal objects
+    pub literal_samples: BTreeMap<String, usize>,
+    /// Augmented samples per class and method, if augmentation is enabled
+    pub augmentation: Option<AugmentStats>,
+    pub class_histogram: BTreeMap<String, usize>,
+    /// Wall-clock time per pipeline stage in seconds
+    pub timings: BTreeMap<&'static str, f32>,
+}
+
+impl RunReport {
+    pub fn new<C: Serialize>(config: &C) -> Self {
+        RunReport {
+            version: env!("CARGO_PKG_VERSION"),
+            config: serde_json::to_value(config).expect("Failed to serialize configuration"),
+            ..Default::default()
+        }
+    }
+
+    pub fn persist(&self, output_dir: &str) {
+        let file = File::create(Path::new(output_dir).join("stats.json"))
+            .expect("Failed to open stats file");
+        serde_json::to_writer_pretty(file, self).expect("Failed to write stats file");
+    }
+}
+
+/// Reasons for an object or sample to be dropped from the dataset
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterReason {
+    EmptyType,
+    TooFewUsages,
+    LambdaType,
+    StructuralType,
+    UnrecoverableAny,
+    SingleAssignment,
+    Duplicate,
+    UnderThreshold,
+    SampleCap,
+    UnknownLabel,
+    NearDuplicate,
+    Contaminated,
+    TokenBudget,
+    Literal,
+    InvalidSliceFile,
+}
+
+impl FilterReason {
+    pub fn description(&self) -> &'static str {
+        match self {
+            FilterReason::EmptyType => "empty type name",
+            FilterReason::TooFewUsages => "fewer usages than lower bound",
+            FilterReason::LambdaType => "lambda type (`=>`)",
+            FilterReason::StructuralType => "structural type (`{`)",
+            FilterReason::UnrecoverableAny => "unrecoverable `ANY` type",
+            FilterReason::SingleAssignment => "single assignment only",
+            FilterReason::Duplicate => "duplicate sample",
+            FilterReason::UnderThreshold => "class under occurrence threshold",
+            FilterReason::SampleCap => "exceeds `max_samples` cap",
+            FilterReason::UnknownLabel => "label missing from label vocabulary",
+            FilterReason::NearDuplicate => "near duplicate of another sample",
+            FilterReason::Contaminated => "matches a held-out sample",
+            FilterReason::TokenBudget => "no usage fits into the token budget",
+            FilterReason::Literal => "literal object",
+            FilterReason::InvalidSliceFile => "slice file that cannot be decoded or parsed",
+        }
+    }
+}
+
+/// Counts dropped objects per filter reason and keeps a uniform sample of the dropped records
+pub struct FilterStats {
+    pub counts: BTreeMap<FilterReason, usize>,
+    samples: BTreeMap<FilterReason, Vec<serde_json::Value>>,
+    samples_per_reason: usize,
+}
+
+impl FilterStats {
+    pub fn new(samples_per_reason: usize) -> Self {
+        FilterStats {
+            counts: BTreeMap::new(),
+            samples: BTreeMap::new(),
+            samples_per_reason,
+        }
+    }
+
+    /// Counts a dropped record, `record` is only evaluated if it ends up in the reservoir sample
+    pub fn record<F>(&mut self, reason: FilterReason, record: F)
+    where
+        F: FnOnce() -> serde_json::Value,
+    {
+        let seen = self.counts.entry(reason).or_insert(0);
+        *seen += 1;
+
+        if self.samples_per_reason == 0 {
+            return;
+        }
+
+        let samples = self.samples.entry(reason).or_default();
+        if samples.len() < self.samples_per_reason {
+            samples.push(record());
+        } else {
+            let j = rand::thread_rng().gen_range(0..*seen);
+            if j < self.samples_per_reason {
+                samples[j] = record();
+            }
+        }
+    }
+
+    /// Counts `n` dropped records without sampling them
+    pub fn record_n(&mut self, reason: FilterReason, n: usize) {
+        *self.counts.entry(reason).or_insert(0) += n;
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    pub fn print_summary(&self) {
+        let total = self.total();
+        println!("[i] Dropped {} objects or samples:", total);
+        for (reason, count) in &self.counts {
+            println!(
+                "    - {:<32} {:>10} ({:.1}%)",
+                reason.description(),
+                count,
+                *count as f32 / total as f32 * 100.0
+            );
+        }
+    }
+
+    /// Writes the sampled records per reason to `filtered_samples.json`
+    pub fn persist_samples(&self, output_dir: &str) {
+        if self.samples_per_reason == 0 {
+            return;
+        }
+
+        let file = File::create(Path::new(output_dir).join("filtered_samples.json"))
+            .expect("Failed to open filter debug file");
+        serde_json::to_writer_pretty(file, &self.samples)
+            .expect("Failed to write filter debug file");
+    }
+}
@@ -0,0 +1,340 @@
+/// Section appended to the template by `--literal-kind` unless it places the literal kind itself
+pub const LITERAL_KIND_SECTION: &str = "[ Literal: {literal_kind} ;]";
+
+/// Section appended to the template by `--siblings` unless it places the siblings itself
+pub const SIBLING_SECTION: &str = "[ Siblings: {siblings} ;]";
+
+/// Section appended to the template by `--subtokens field` unless it places the sub-tokens itself
+pub const SUBTOKEN_SECTION: &str = "[ Subtokens: {subtokens| } ;]";
+
+/// Layout of the features before templates were configurable
+pub const DEFAULT_TEMPLATE: &str = "Variable: {variable} ; Scope: {scope} ;[ Calls: {calls} ;][ Argument to: {arg_tos} ;][ Language: {language} ;]";
+
+const DEFAULT_SEPARATOR: &str = ", ";
+
+/// Values a template placeholder can refer to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Variable,
+    Scope,
+    Calls,
+    ArgTos,
+    Language,
+    /// Return types of the calls invoked on the object
+    ReturnTypes,
+    /// Unique sub-tokens of variable, scope and usages
+    Subtokens,
+    /// Kind of the literal the object is or is defined by, such as `string` or `number`
+    LiteralKind,
+    /// Variables of the same scope that share calls with the object
+    Siblings,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "variable" => Some(Field::Variable),
+            "scope" => Some(Field::Scope),
+            "calls" => Some(Field::Calls),
+            "arg_tos" => Some(Field::ArgTos),
+            "language" => Some(Field::Language),
+            "return_types" => Some(Field::ReturnTypes),
+            "subtokens" => Some(Field::Subtokens),
+            "literal_kind" => Some(Field::LiteralKind),
+            "siblings" => Some(Field::Siblings),
+            _ => None,
+        }
+    }
+}
+
+/// Values of the placeholders of one sample
+pub struct FeatureFields<'a> {
+    pub variable: &'a str,
+    pub scope: &'a str,
+    pub calls: &'a [String],
+    pub arg_tos: &'a [String],
+    pub language: Option<&'a str>,
+    pub return_types: &'a [String],
+    pub literal_kind: Option<&'a str>,
+    pub siblings: &'a [String],
+    pub subtokens: &'a [String],
+}
+
+impl FeatureFields<'_> {
+    fn values(&self, field: Field) -> &[String] {
+        match field {
+            Field::Calls => self.calls,
+            Field::ArgTos => self.arg_tos,
+            Field::ReturnTypes => self.return_types,
+            Field::Subtokens => self.subtokens,
+            Field::Siblings => self.siblings,
+            Field::Variable | Field::Scope | Field::Language | Field::LiteralKind => &[],
+        }
+    }
+
+    fn is_empty(&self, field: Field) -> bool {
+        match field {
+            Field::Variable => self.variable.is_empty(),
+            Field::Scope => self.scope.is_empty(),
+            Field::Language => self.language.is_none(),
+            Field::LiteralKind => self.literal_kind.is_none(),
+            _ => self.values(field).is_empty(),
+        }
+    }
+
+    fn push(&self, field: Field, separator: &str, out: &mut String) {
+        match field {
+            Field::Variable => out.push_str(self.variable),
+            Field::Scope => out.push_str(self.scope),
+            Field::Language => out.push_str(self.language.unwrap_or("")),
+            Field::LiteralKind => out.push_str(self.literal_kind.unwrap_or("")),
+            _ => out.push_str(&self.values(field).join(separator)),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Segment {
+    Text(String),
+    Placeholder { field: Field, separator: String },
+}
+
+#[derive(Debug)]
+struct Section {
+    segments: Vec<Segment>,
+    optional: bool,
+}
+
+/// Template of the feature strings. `{field}` is replaced with the value of a field, list fields
+/// are joined with `, ` or the separator given as `{field|separator}`. A section in `[...]` is
+/// omitted if all of its fields are empty. `\` escapes the next character.
+///
+/// Near-duplicate detection, augmentation and decontamination work on the field values of a sample
+/// and re-render the template, so they do not depend on its layout.
+#[derive(Debug)]
+pub struct FeatureTemplate {
+    sections: Vec<Section>,
+}
+
+impl Default for FeatureTemplate {
+    fn default() -> Self {
+        FeatureTemplate::parse(DEFAULT_TEMPLATE).unwrap()
+    }
+}
+
+impl FeatureTemplate {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut sections = Vec::new();
+        let mut current = Section {
+            segments: Vec::new(),
+            optional: false,
+        };
+        let mut text = String::new();
+
+        let mut chars = source.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(escaped) => text.push(escaped),
+                    None => return Err("Template ends with an escape character".to_owned()),
+                },
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => {
+                                return Err(format!("Unclosed placeholder '{{{}'", placeholder))
+                            }
+                        }
+                    }
+
+                    let (name, separator) = placeholder
+                        .split_once('|')
+                        .unwrap_or((&placeholder, DEFAULT_SEPARATOR));
+                    let field = Field::parse(name.trim())
+                        .ok_or_else(|| format!("Unknown template field '{}'", name.trim()))?;
+
+                    if !text.is_empty() {
+                        current
+                            .segments
+                            .push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    current.segments.push(Segment::Placeholder {
+                        field,
+                        separator: separator.to_owned(),
+                    });
+                }
+                '[' | ']' => {
+                    if (c == '[') == current.optional {
+                        return Err(format!("Unbalanced '{}', sections cannot be nested", c));
+                    }
+
+                    if !text.is_empty() {
+                        current
+                            .segments
+                            .push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    let next = Section {
+                        segments: Vec::new(),
+                        optional: c == '[',
+                    };
+                    sections.push(std::mem::replace(&mut current, next));
+                }
+                '}' => return Err("Unbalanced '}'".to_owned()),
+                c => text.push(c),
+            }
+        }
+
+        if current.optional {
+            return Err("Unclosed section '['".to_owned());
+        }
+        if !text.is_empty() {
+            current.segments.push(Segment::Text(text));
+        }
+        sections.push(current);
+        sections.retain(|s| !s.segments.is_empty());
+
+        Ok(FeatureTemplate { sections })
+    }
+
+    /// Whether a field is referenced by the template
+    pub fn uses(&self, field: Field) -> bool {
+        self.sections.iter().any(|s| {
+            s.segments
+                .iter()
+                .any(|seg| matches!(seg, Segment::Placeholder { field: f, .. } if *f == field))
+        })
+    }
+
+    pub fn render(&self, fields: &FeatureFields) -> String {
+        let mut out = String::new();
+        for section in &self.sections {
+            if section.optional {
+                let placeholders: Vec<Field> = section
+                    .segments
+                    .iter()
+                    .filter_map(|seg| match seg {
+                        Segment::Placeholder { field, .. } => Some(*field),
+                        Segment::Text(_) => None,
+                    })
+                    .collect();
+                if !placeholders.is_empty() && placeholders.iter().all(|f| fields.is_empty(*f)) {
+                    continue;
+                }
+            }
+
+            for segment in &section.segments {
+                match segment {
+                    Segment::Text(text) => out.push_str(text),
+                    Segment::Placeholder { field, separator } => {
+                        fields.push(*field, separator, &mut out)
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn fields<'a>(
+        calls: &'a [String],
+        arg_tos: &'a [String],
+        language: Option<&'a str>,
+    ) -> FeatureFields<'a> {
+        FeatureFields {
+            variable: "user",
+            scope: "app.ts::load",
+            calls,
+            arg_tos,
+            language,
+            return_types: &[],
+            literal_kind: None,
+            siblings: &[],
+            subtokens: &[],
+        }
+    }
+
+    /// Feature layout before templates were configurable
+    fn legacy_format(calls: &[String], arg_tos: &[String], language: Option<&str>) -> String {
+        let call_names = if !calls.is_empty() {
+            format!(" Calls: {} ;", calls.join(", "))
+        } else {
+            "".to_string()
+        };
+        let arg_names = if !arg_tos.is_empty() {
+            format!(" Argument to: {} ;", arg_tos.join(", "))
+        } else {
+            "".to_string()
+        };
+        let lang = if let Some(l) = language {
+            format!(" Language: {} ;", l)
+        } else {
+            "".to_string()
+        };
+        format!(
+            "Variable: {} ; Scope: {} ;{}{}{}",
+            "user", "app.ts::load", call_names, arg_names, lang
+        )
+    }
+
+    #[test]
+    fn default_template_matches_legacy_format() {
+        let template = FeatureTemplate::default();
+        let usages = [
+            strings(&[]),
+            strings(&["fetch"]),
+            strings(&["fetch", "then", "catch"]),
+        ];
+        for calls in &usages {
+            for arg_tos in &usages {
+                for language in [None, Some(""), Some("typescript")] {
+                    assert_eq!(
+                        template.render(&fields(calls, arg_tos, language)),
+                        legacy_format(calls, arg_tos, language)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn separators_and_optional_sections() {
+        let template =
+            FeatureTemplate::parse("{variable}[ <{calls|/}>][ \\[{arg_tos}\\]]").unwrap();
+        let calls = strings(&["a", "b"]);
+        let arg_tos = strings(&["f"]);
+
+        assert_eq!(
+            template.render(&fields(&calls, &arg_tos, None)),
+            "user <a/b> [f]"
+        );
+        assert_eq!(template.render(&fields(&[], &[], None)), "user");
+        assert!(template.uses(Field::Calls));
+        assert!(!template.uses(Field::Scope));
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        for source in [
+            "{variable",
+            "{unknown}",
+            "[{calls}",
+            "{calls}]",
+            "[[{calls}]]",
+            "}",
+            "{variable}\\",
+        ] {
+            assert!(FeatureTemplate::parse(source).is_err(), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+
+/// Tokenizers available to estimate the length of features
+#[derive(clap::ValueEnum, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TokenizerKind {
+    /// Count whitespace-separated words
+    #[default]
+    Whitespace,
+    /// Apply the merges of a BPE vocabulary, `merges.txt` or `tokenizer.json`
+    Bpe,
+}
+
+/// Merges of a `merges.txt`, one pair separated by a space per line
+fn text_merges(content: &str) -> Result<Vec<(String, String)>, String> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.starts_with("#version") && !l.trim().is_empty())
+        .map(|(i, l)| match l.split(' ').collect::<Vec<_>>()[..] {
+            [a, b] if !a.is_empty() && !b.is_empty() => Ok((a.to_owned(), b.to_owned())),
+            _ => Err(format!("line {} is not a pair of symbols", i + 1)),
+        })
+        .collect()
+}
+
+/// Merges of a `tokenizer.json`, pairs are written as `"a b"` or `["a", "b"]`
+fn json_merges(content: &str) -> Result<Vec<(String, String)>, String> {
+    let tokenizer: serde_json::Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    tokenizer["model"]["merges"]
+        .as_array()
+        .ok_or("no merges in 'model'")?
+        .iter()
+        .enumerate()
+        .map(|(i, merge)| {
+            let pair = match merge {
+                serde_json::Value::String(s) => s.split_once(' '),
+                serde_json::Value::Array(pair) => match pair.as_slice() {
+                    [serde_json::Value::String(a), serde_json::Value::String(b)] => {
+                        Some((a.as_str(), b.as_str()))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            };
+            pair.map(|(a, b)| (a.to_owned(), b.to_owned()))
+                .ok_or(format!("merge {} is not a pair of symbols", i))
+        })
+        .collect()
+}
+
+/// Byte-pair encoding with the merge ranks of a GPT-2 style vocabulary. Words are split at
+/// character class boundaries and a leading space is encoded as `Ġ`, as done by byte-level BPE.
+pub struct Bpe {
+    ranks: HashMap<(String, String), usize>,
+    /// Token counts of previously seen words, call and scope names repeat a lot
+    cache: RefCell<HashMap<String, usize>>,
+}
+
+impl Bpe {
+    /// Loads the merges of a `merges.txt` or a Hugging Face `tokenizer.json`
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read BPE vocabulary '{}': {}", path, e))?;
+        let merges = match path.ends_with(".json") {
+            true => json_merges(&content),
+            false => text_merges(&content),
+        }
+        .map_err(|e| format!("Invalid BPE vocabulary '{}': {}", path, e))?;
+        if merges.is_empty() {
+            return Err(format!("Invalid BPE vocabulary '{}': no merges", path));
+        }
+
+        Ok(Bpe {
+            ranks: merges
+                .into_iter()
+                .enumerate()
+                .map(|(rank, pair)| (pair, rank))
+                .collect(),
+            cache: RefCell::new(HashMap::new()),
+        })
+    }
+
+    fn count_word(&self, word: &str) -> usize {
+        if let Some(n) = self.cache.borrow().get(word) {
+            return *n;
+        }
+
+        let mut symbols: Vec<String> = word.chars().map(String::from).collect();
+        loop {
+            // merge the pair with the lowest rank, everywhere in the word
+            let best = symbols
+                .windows(2)
+                .filter_map(|w| self.ranks.get(&(w[0].to_owned(), w[1].to_owned())))
+                .min();
+            let best = match best {
+                Some(rank) => *rank,
+                None => break,
+            };
+
+            let mut merged = Vec::with_capacity(symbols.len());
+            let mut i = 0;
+            while i < symbols.len() {
+                if i + 1 < symbols.len()
+                    && self
+                        .ranks
+                        .get(&(symbols[i].to_owned(), symbols[i + 1].to_owned()))
+                        == Some(&best)
+                {
+                    merged.push(format!("{}{}", symbols[i], symbols[i + 1]));
+                    i += 2;
+                } else {
+                    merged.push(symbols[i].to_owned());
+                    i += 1;
+                }
+            }
+            symbols = merged;
+        }
+
+        self.cache
+            .borrow_mut()
+            .insert(word.to_owned(), symbols.len());
+        symbols.len()
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        let class = |c: char| {
+            if c.is_alphabetic() {
+                0
+            } else if c.is_numeric() {
+                1
+            } else {
+                2
+            }
+        };
+
+        let mut count = 0;
+        let mut word = String::new();
+        let mut prev: Option<char> = None;
+        for c in text.chars() {
+            if c == ' ' {
+                if !word.is_empty() {
+                    count += self.count_word(&word);
+                    word.clear();
+                }
+                word.push('Ġ');
+            } else {
+                let boundary = prev.is_some_and(|p| p != ' ' && class(p) != class(c));
+                if boundary && !word.is_empty() {
+                    count += self.count_word(&word);
+                    word.clear();
+                }
+                word.push(c);
+            }
+            prev = Some(c);
+        }
+        if !word.is_empty() {
+            count += self.count_word(&word);
+        }
+        count
+    }
+}
+
+/// Estimates the number of tokens a model sees for a feature
+pub enum Tokenizer {
+    Whitespace,
+    Bpe(Bpe),
+}
+
+impl Tokenizer {
+    pub fn new(kind: TokenizerKind, vocab: Option<&str>) -> Result<Self, String> {
+        match (kind, vocab) {
+            (TokenizerKind::Whitespace, _) => Ok(Tokenizer::Whitespace),
+            (TokenizerKind::Bpe, Some(vocab)) => Bpe::load(vocab).map(Tokenizer::Bpe),
+            (TokenizerKind::Bpe, None) => {
+                Err("The BPE tokenizer requires a vocabulary, see `--bpe-vocab`".to_owned())
+            }
+        }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        match self {
+            Tokenizer::Whitespace => text.split_whitespace().count(),
+            Tokenizer::Bpe(bpe) => bpe.count(text),
+        }
+    }
+}
+
+/// Maximum number of tokens per feature
+pub struct TokenBudget {
+    pub tokenizer: Tokenizer,
+    pub max_tokens: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Writes a vocabulary to a temporary file with the given file name suffix
+    fn vocab(suffix: &str, content: &str) -> String {
+        static NUM_FILES: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "extys-bpe-{}-{}-{}",
+            std::process::id(),
+            NUM_FILES.fetch_add(1, Ordering::Relaxed),
+            suffix
+        ));
+        fs::write(&path, content).unwrap();
+        path.display().to_string()
+    }
+
+    fn load(suffix: &str, content: &str) -> Result<Bpe, String> {
+        Bpe::load(&vocab(suffix, content))
+    }
+
+    #[test]
+    fn merges_are_applied_by_rank() {
+        let bpe = load("merges.txt", "#version: 0.2\ng e\nge t\nĠ u\ns e\nse r\n").unwrap();
+
+        assert_eq!(bpe.count("get"), 1);
+        // `Ġu` and `ser` are merged, `Ġus` is not
+        assert_eq!(bpe.count("get user"), 3);
+        // words are split at character classes, a leading space is not merged into `get`
+        assert_eq!(bpe.count("get2get"), 3);
+        assert_eq!(bpe.count("get2 get"), 4);
+        assert_eq!(bpe.count("user"), 2);
+    }
+
+    #[test]
+    fn merges_are_read_from_tokenizer_json() {
+        let content = r#"{"model": {"type": "BPE", "merges": ["g e", ["ge", "t"], "Ġ u"]}}"#;
+        let bpe = load("tokenizer.json", content).unwrap();
+
+        assert_eq!(bpe.count("get user"), 1 + 4);
+        // `Variable`, `:` and `Ġ` `get`
+        assert_eq!(bpe.count("Variable: get"), 8 + 1 + 2);
+    }
+
+    #[test]
+    fn invalid_vocabularies_are_rejected() {
+        let missing = std::env::temp_dir().join("extys-bpe-missing-merges.txt");
+        assert!(Bpe::load(missing.to_str().unwrap()).is_err());
+
+        for (suffix, content) in [
+            ("merges.txt", "#version: 0.2\n"),
+            ("merges.txt", "g e\nget\n"),
+            ("merges.txt", "g e x\n"),
+            ("tokenizer.json", "{\"model\": {\"merges\": "),
+            ("tokenizer.json", r#"{"model": {"vocab": {}}}"#),
+            (
+                "tokenizer.json",
+                r#"{"model": {"merges": ["g e", ["ge"]]}}"#,
+            ),
+            ("tokenizer.json", r#"{"model": {"merges": ["g e", 3]}}"#),
+        ] {
+            assert!(load(suffix, content).is_err(), "{:?}", content);
+        }
+        assert!(Tokenizer::new(TokenizerKind::Bpe, None).is_err());
+    }
+}
@@ -2,13 +2,79 @@
 #![allow(unused_variables)]
 #![allow(unused_imports)]
 
-use crate::slice_structs::{Call, ObjSlice};
+use crate::slice_structs::{ArgTo, FeatureRecord, FeatureValues, ObjSlice, Sample};
+use crate::template::{FeatureFields, FeatureTemplate, Field};
+use crate::tokenizer::TokenBudget;
+use itertools::Itertools;
 use memchr::memmem;
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{self, File};
 use std::io::prelude::*;
-use std::{cmp::min, num};
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Source language of the processed slices, selects the applicable normalization rules
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    TypeScript,
+    Python,
+    Java,
+}
+
+impl Language {
+    pub fn from_arg(lang: &Option<String>) -> Self {
+        match lang.as_deref().map(str::to_lowercase).as_deref() {
+            Some("python") => Language::Python,
+            Some("java") | Some("jvm") | Some("kotlin") | Some("scala") => Language::Java,
+            _ => Language::TypeScript,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::TypeScript => "typescript",
+            Language::Python => "python",
+            Language::Java => "java",
+        }
+    }
+}
+
+/// How much of a parameterized type is kept in the class label
+#[derive(clap::ValueEnum, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LabelGranularity {
+    /// Base type only, `Map<string, User>` -> `Map`
+    #[default]
+    Base,
+    /// Keep the type arguments of the outermost type, `Promise<Array<string>>` -> `Promise<Array>`
+    OneLevel,
+    /// Keep the fully normalized generic type
+    Full,
+}
+
+impl LabelGranularity {
+    /// Number of nested type argument levels kept in the label
+    pub fn depth(&self) -> usize {
+        match self {
+            LabelGranularity::Base => 0,
+            LabelGranularity::OneLevel => 1,
+            LabelGranularity::Full => usize::MAX,
+        }
+    }
+}
 
 pub struct Parser<'a> {
+    pub lang: Language,
+    pub label_granularity: LabelGranularity,
+    /// Strip package qualifiers from JVM type and method names
+    pub shorten_packages: bool,
+    /// Layout of the assembled features
+    pub feature_template: FeatureTemplate,
+    /// Method names longer than this many bytes lose type assertions and arguments
+    pub max_name_len: usize,
+    /// If set, usages are packed into features of at most this many tokens
+    pub token_budget: Option<TokenBudget>,
     pub finder_eq: memmem::Finder<'a>,
     pub finder_newline: memmem::Finder<'a>,
     pub finder_pipe: memmem::Finder<'a>,
@@ -29,17 +95,20 @@ pub struct Parser<'a> {
 
 impl Parser<'_> {
     pub fn new(lang: &Option<String>) -> Self {
-        let import_sep = if let Some(l) = lang {
-            if l.to_lowercase().eq("python") {
-                ".py:"
-            } else {
-                ".ts::program:"
-            }
-        } else {
-            ".ts::program:"
+        let lang = Language::from_arg(lang);
+        let import_sep = match lang {
+            Language::Python => ".py:",
+            Language::Java => ".java:",
+            Language::TypeScript => ".ts::program:",
         };
 
         Parser {
+            lang,
+            label_granularity: LabelGranularity::Base,
+            shorten_packages: false,
+            feature_template: FeatureTemplate::default(),
+            max_name_len: 48,
+            token_budget: None,
             finder_eq: memmem::Finder::new("="),
             finder_newline: memmem::Finder::new("\n"),
             finder_pipe: memmem::Finder::new("|"),
@@ -63,12 +132,52 @@ impl Parser<'_> {
 /// Performs denoising on the type name and local resolution imports and returns multiple flattened types in case of a union
 #[inline(always)]
 pub fn clean_type(parser: &Parser, name: &str) -> Vec<String> {
+    clean_type_to_depth(parser, name, parser.label_granularity.depth())
+}
+
+/// Class label of a type. The members of a union are sorted and joined with ` | `, so that
+/// `Union[str, int]` and `int | str` both become `int | str` instead of their first member
+pub fn type_label(parser: &Parser, name: &str) -> String {
+    union_label(clean_type(parser, name))
+}
+
+fn union_label(mut members: Vec<String>) -> String {
+    members.sort();
+    members.dedup();
+    members.join(" | ")
+}
+
+/// Cleans the type name while keeping `depth` levels of type arguments, each argument is cleaned recursively
+pub fn clean_type_to_depth(parser: &Parser, name: &str, depth: usize) -> Vec<String> {
+    match parser.lang {
+        Language::Java => vec![clean_java_type(parser, name, depth)],
+        Language::Python => clean_python_type(parser, name, depth),
+        Language::TypeScript => vec![clean_ts_type(parser, name, depth)],
+    }
+}
+
+fn clean_ts_type(parser: &Parser, name: &str, depth: usize) -> String {
+    if depth > 0 {
+        let trimmed = name.trim();
+        let (base, args) = match trimmed.strip_suffix("[]") {
+            Some(elem) => ("Array", vec![elem]),
+            None => split_type_args(trimmed, '<', '>'),
+        };
+
+        if !args.is_empty() {
+            let args: Vec<String> = args
+                .iter()
+                .map(|a| clean_ts_type(parser, a, depth - 1))
+                .collect();
+            return format!("{}<{}>", clean_ts_type(parser, base, 0), args.join(", "));
+        }
+    }
+
     let mut new_name = name.to_string();
 
     if name.starts_with("<export") {
-        match memmem::rfind(name.as_bytes(), "/".as_bytes()) {
-            Some(i) => new_name = format!("<export>::{}", name[i + 1..].to_string()),
-            None => {}
+        if let Some(i) = memmem::rfind(name.as_bytes(), "/".as_bytes()) {
+            new_name = format!("<export>::{}", &name[i + 1..])
         };
     } else {
         if name.ends_with("[]")
@@ -82,13 +191,7 @@ pub fn clean_type(parser: &Parser, name: &str) -> Vec<String> {
             // strip generics
             while let Some(i_o) = parser.finder_angle_bracket_o.find(new_name.as_bytes()) {
                 match memmem::rfind(new_name.as_bytes(), ">".as_bytes()) {
-                    Some(i_c) => {
-                        new_name = format!(
-                            "{}{}",
-                            new_name[..i_o].to_string(),
-                            new_name[i_c + 1..].to_string()
-                        )
-                    }
+                    Some(i_c) => new_name = format!("{}{}", &new_name[..i_o], &new_name[i_c + 1..]),
                     None => break,
                 }
             }
@@ -113,23 +216,19 @@ pub fn clean_type(parser: &Parser, name: &str) -> Vec<String> {
                     }
                 }
 
-                new_name = format!("{}.{}", left_side.to_string(), right_side.to_string());
+                new_name = format!("{}.{}", left_side, right_side);
             } else if let Some(i_ts) = parser.finder_import.find(new_name.as_bytes()) {
                 if let Some(i_col) = memmem::rfind(new_name.as_bytes(), ":".as_bytes()) {
                     if let Some(i_slash) =
-                        memmem::rfind(new_name[..i_ts].as_bytes(), "/".as_bytes())
+                        memmem::rfind(&new_name.as_bytes()[..i_ts], "/".as_bytes())
                     {
                         new_name = format!(
                             "{}.{}",
-                            new_name[i_slash + 1..i_ts].to_string(),
-                            new_name[i_col + 1..].to_string()
+                            &new_name[i_slash + 1..i_ts],
+                            &new_name[i_col + 1..]
                         );
                     } else {
-                        new_name = format!(
-                            "{}.{}",
-                            new_name[..i_ts].to_string(),
-                            new_name[i_col + 1..].to_string()
-                        );
+                        new_name = format!("{}.{}", &new_name[..i_ts], &new_name[i_col + 1..]);
                     }
                 }
             }
@@ -169,70 +268,500 @@ pub fn clean_type(parser: &Parser, name: &str) -> Vec<String> {
         }
     }
 
-    vec![new_name]
+    new_name
 }
 
-pub fn merge_common_types(usage_vectors: &mut Vec<(String, String, usize)>) {
-    println!("[*] Merging most common type synonyms");
+/// Splits `name` at every top-level occurrence of `sep`, ignoring separators nested in brackets
+pub fn split_top_level(name: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth: i32 = 0;
+    let mut start = 0;
 
-    for sample in usage_vectors.iter_mut() {
-        let curr_type_label = sample.1.to_owned();
+    for (i, c) in name.char_indices() {
+        match c {
+            '[' | '<' | '(' => depth += 1,
+            ']' | '>' | ')' => depth -= 1,
+            _ if c == sep && depth == 0 => {
+                parts.push(name[start..i].trim());
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(name[start..].trim());
 
-        if matches!(
-            curr_type_label.as_str(),
-            "__ecma.String" | "String" | "$String" | "types.__String"
-        ) || curr_type_label.starts_with("string | ")
-        {
-            sample.1 = "string".to_string();
-        } else if matches!(curr_type_label.as_str(), "__ecma.Number" | "Number")
-            || curr_type_label.starts_with("number | ")
-        {
-            sample.1 = "number".to_string();
-        } else if matches!(
-            curr_type_label.as_str(),
-            "__ecma.Boolean" | "boolean" | "Boolean" | "Bool" | "BOOLEAN"
-        ) {
-            sample.1 = "bool".to_string();
-        } else if matches!(
-            curr_type_label.as_str(),
-            "__ecma.Object"
-                | "object."
-                | "types.ObjectType"
-                | "ObjectType"
-                | "Object"
-                | "AnyObject"
-        ) || curr_type_label.ends_with(" | object")
-        {
-            sample.1 = "object".to_string();
-        } else if matches!(
-            curr_type_label.as_str(),
-            "__ecma.Null" | "Null" | "Nullable"
-        ) {
-            sample.1 = "null".to_string();
-        } else if matches!(curr_type_label.as_str(), "__ecma.Date" | "Date") {
-            sample.1 = "date".to_string();
-        } else if matches!(curr_type_label.as_str(), "__ecma.Set" | "Set") {
-            sample.1 = "set".to_string();
-        } else if matches!(
-            curr_type_label.as_str(),
-            "__ecma.Symbol" | "ts.Symbol" | "Symbol" | "types.Symbol"
-        ) {
-            sample.1 = "symbol".to_string();
-        } else if matches!(
-            curr_type_label.as_str(),
-            "__ecma.Map" | "map." | "Map" | "types.Map"
-        ) {
-            sample.1 = "map".to_string();
-        } else if matches!(curr_type_label.as_str(), "__ecma.Promise" | "Promise") {
-            sample.1 = "promise".to_string();
-        } else if matches!(curr_type_label.as_str(), "__ecma.Error" | "Error" | "ERROR") {
-            sample.1 = "error".to_string();
+    parts
+}
+
+/// Splits a parameterized type into its base and top-level type arguments, e.g. `Dict[str, List[int]]` -> (`Dict`, [`str`, `List[int]`])
+pub fn split_type_args(name: &str, open: char, close: char) -> (&str, Vec<&str>) {
+    match name.find(open) {
+        Some(i_o) if i_o > 0 && name.ends_with(close) => {
+            let inner = name[i_o + open.len_utf8()..name.len() - close.len_utf8()].trim();
+            let args = if inner.is_empty() {
+                Vec::new()
+            } else {
+                split_top_level(inner, ',')
+            };
+            (name[..i_o].trim_end(), args)
+        }
+        _ => (name, Vec::new()),
+    }
+}
+
+/// Maps builtin and `typing` qualified names onto their canonical builtin name, e.g. `typing.List` -> `list`
+pub fn canonical_python_name(name: &str) -> &str {
+    let mut base = name;
+    for prefix in [
+        "typing.",
+        "typing_extensions.",
+        "builtins.",
+        "__builtin.",
+        "__builtins__.",
+    ] {
+        if let Some(stripped) = base.strip_prefix(prefix) {
+            base = stripped;
         }
     }
+
+    match base {
+        "List" => "list",
+        "Dict" | "DefaultDict" | "OrderedDict" => "dict",
+        "Set" => "set",
+        "FrozenSet" => "frozenset",
+        "Tuple" => "tuple",
+        "Type" => "type",
+        "Text" | "AnyStr" => "str",
+        "NoneType" => "None",
+        _ => base,
+    }
+}
+
+/// Python specific denoising: resolves module imports, canonicalizes builtins and `typing` aliases,
+/// unwraps `Optional` and returns the members of a `Union` as multiple flattened types
+pub fn clean_python_type(parser: &Parser, name: &str, depth: usize) -> Vec<String> {
+    let mut new_name = name
+        .trim()
+        .replace(&['\"', '\\', '\'', '\n', '\t', '\r'][..], "");
+
+    // resolve local imports, `app/models.py:<module>.User` -> `models.User`
+    if let Some(i_py) = parser.finder_import.find(new_name.as_bytes()) {
+        let module = match memmem::rfind(&new_name.as_bytes()[..i_py], "/".as_bytes()) {
+            Some(i_slash) => &new_name[i_slash + 1..i_py],
+            None => &new_name[..i_py],
+        };
+        new_name = format!("{}.{}", module, &new_name[i_py + ".py:".len()..]);
+    }
+
+    new_name = new_name
+        .replace("<module>.", "")
+        .replace("..", ".")
+        .replace(".__init__", "");
+    if let Some(prefix) = new_name.strip_suffix(".<module>") {
+        new_name = prefix.to_string();
+    }
+
+    // PEP 604 unions, e.g. `str | None`
+    let members = split_top_level(&new_name, '|');
+    if members.len() > 1 {
+        return flatten_python_union(parser, &members, depth);
+    }
+
+    let (base, args) = split_type_args(&new_name, '[', ']');
+    match canonical_python_name(base) {
+        "Optional" if args.len() == 1 => clean_python_type(parser, args[0], depth),
+        "Union" if !args.is_empty() => flatten_python_union(parser, &args, depth),
+        base if depth > 0 && !args.is_empty() => {
+            let args: Vec<String> = args
+                .iter()
+                .map(|a| union_label(clean_python_type(parser, a, depth - 1)))
+                .collect();
+            vec![format!("{}[{}]", base, args.join(", "))]
+        }
+        base => vec![base.to_string()],
+    }
+}
+
+/// Cleans every member of a union, omitting `None` unless it is the only member
+fn flatten_python_union(parser: &Parser, members: &[&str], depth: usize) -> Vec<String> {
+    let types: Vec<String> = members
+        .iter()
+        .flat_map(|m| clean_python_type(parser, m, depth))
+        .filter(|t| t != "None")
+        .collect();
+
+    if types.is_empty() {
+        vec!["None".to_string()]
+    } else {
+        types
+    }
+}
+
+/// Method reference parsed from a JVM method full name such as `java.util.List.add:boolean(java.lang.Object)`
+#[derive(Debug, PartialEq, Eq)]
+pub struct JavaSignature<'a> {
+    pub class: &'a str,
+    pub method: &'a str,
+    pub return_type: &'a str,
+    pub param_types: Vec<&'a str>,
+}
+
+/// Splits a JVM method full name into declaring class, method name, return and parameter types
+pub fn parse_java_signature(full_name: &str) -> Option<JavaSignature<'_>> {
+    let i_col = memchr::memchr(b':', full_name.as_bytes())?;
+    let (qualified, signature) = (&full_name[..i_col], &full_name[i_col + 1..]);
+
+    let (class, method) = match memmem::rfind(qualified.as_bytes(), ".".as_bytes()) {
+        Some(i) => (&qualified[..i], &qualified[i + 1..]),
+        None => ("", qualified),
+    };
+
+    let (return_type, param_types) = match (signature.find('('), signature.rfind(')')) {
+        (Some(i_o), Some(i_c)) if i_o < i_c => {
+            let params = signature[i_o + 1..i_c].trim();
+            let param_types = if params.is_empty() {
+                Vec::new()
+            } else {
+                params.split(',').map(str::trim).collect()
+            };
+            (&signature[..i_o], param_types)
+        }
+        _ => (signature, Vec::new()),
+    };
+
+    Some(JavaSignature {
+        class,
+        method,
+        return_type,
+        param_types,
+    })
+}
+
+/// Drops the package qualifier of a JVM class name but keeps enclosing classes, e.g. `java.util.Map.Entry` -> `Map.Entry`
+pub fn shorten_java_name(name: &str) -> &str {
+    let mut start = 0;
+    for segment in name.split('.') {
+        if segment.starts_with(|c: char| c.is_uppercase() || c == '<') {
+            return &name[start..];
+        }
+        start += segment.len() + 1;
+    }
+
+    match memmem::rfind(name.as_bytes(), ".".as_bytes()) {
+        Some(i) => &name[i + 1..],
+        None => name,
+    }
+}
+
+/// Normalizes JVM type full names: keeps the package of generic types, flattens nested classes and arrays
+pub fn clean_java_type(parser: &Parser, name: &str, depth: usize) -> String {
+    let mut new_name = name.trim();
+
+    if let Some(stripped) = new_name.strip_prefix("<unresolvedNamespace>.") {
+        new_name = stripped;
+    }
+
+    if depth > 0 {
+        let (base, args) = match new_name.strip_suffix("[]") {
+            Some(elem) => ("Array", vec![elem]),
+            None => split_type_args(new_name, '<', '>'),
+        };
+
+        if !args.is_empty() {
+            let args: Vec<String> = args
+                .iter()
+                .map(|a| clean_java_type(parser, a, depth - 1))
+                .collect();
+            return format!("{}<{}>", clean_java_type(parser, base, 0), args.join(", "));
+        }
+    }
+
+    if new_name.ends_with("[]") {
+        return "Array".to_string();
+    }
+
+    // strip type arguments, `java.util.List<java.lang.String>` -> `java.util.List`
+    if let Some(i) = parser.finder_angle_bracket_o.find(new_name.as_bytes()) {
+        if i > 0 {
+            new_name = new_name[..i].trim_end();
+        }
+    }
+
+    let new_name = new_name.replace('$', ".");
+    if parser.shorten_packages {
+        shorten_java_name(&new_name).to_string()
+    } else {
+        new_name
+    }
+}
+
+/// Reduces JVM method full names to `Class.method`, dropping the signature
+pub fn clean_java_method_name(parser: &Parser, name: &str) -> Option<String> {
+    if name.is_empty() || parser.finder_newline.find(name.as_bytes()).is_some() {
+        return None;
+    }
+
+    if name.starts_with("<operator") {
+        return match parser.finder_op_close.find(name.as_bytes()) {
+            Some(i) => Some(name[i + 2..].to_string()),
+            None => Some(name.to_string()),
+        };
+    }
+
+    match parse_java_signature(name) {
+        Some(sig) => {
+            let class = clean_java_type(parser, sig.class, 0);
+            if class.is_empty() || class.starts_with('<') {
+                Some(sig.method.to_string())
+            } else {
+                Some(format!("{}.{}", class, sig.method))
+            }
+        }
+        None => Some(name.to_string()),
+    }
+}
+
+/// Merges the most common type synonyms of a cleaned label, including boxed JVM types for Java
+pub fn merge_synonyms(parser: &Parser, label: &str) -> String {
+    let label = if parser.lang == Language::Java {
+        merge_type_parts(label, boxed_type_synonym)
+    } else {
+        label.to_string()
+    };
+
+    merge_type_parts(&label, common_type_synonym)
+}
+
+/// Canonical name of common type synonyms, e.g. `__ecma.String` -> `string`
+pub fn common_type_synonym(curr_type_label: &str) -> Option<&'static str> {
+    if matches!(
+        curr_type_label,
+        "__ecma.String" | "String" | "$String" | "types.__String"
+    ) || curr_type_label.starts_with("string | ")
+    {
+        Some("string")
+    } else if matches!(curr_type_label, "__ecma.Number" | "Number")
+        || curr_type_label.starts_with("number | ")
+    {
+        Some("number")
+    } else if matches!(
+        curr_type_label,
+        "__ecma.Boolean" | "boolean" | "Boolean" | "Bool" | "BOOLEAN"
+    ) {
+        Some("bool")
+    } else if matches!(
+        curr_type_label,
+        "__ecma.Object" | "object." | "types.ObjectType" | "ObjectType" | "Object" | "AnyObject"
+    ) || curr_type_label.ends_with(" | object")
+    {
+        Some("object")
+    } else if matches!(curr_type_label, "__ecma.Null" | "Null" | "Nullable") {
+        Some("null")
+    } else if matches!(curr_type_label, "__ecma.Date" | "Date") {
+        Some("date")
+    } else if matches!(curr_type_label, "__ecma.Set" | "Set") {
+        Some("set")
+    } else if matches!(
+        curr_type_label,
+        "__ecma.Symbol" | "ts.Symbol" | "Symbol" | "types.Symbol"
+    ) {
+        Some("symbol")
+    } else if matches!(curr_type_label, "__ecma.Map" | "map." | "Map" | "types.Map") {
+        Some("map")
+    } else if matches!(curr_type_label, "__ecma.Promise" | "Promise") {
+        Some("promise")
+    } else if matches!(curr_type_label, "__ecma.Error" | "Error" | "ERROR") {
+        Some("error")
+    } else {
+        None
+    }
+}
+
+/// Primitive counterpart of a boxed JVM type, e.g. `java.lang.Integer` -> `int`
+pub fn boxed_type_synonym(curr_type_label: &str) -> Option<&'static str> {
+    let curr_type_label = curr_type_label
+        .strip_prefix("java.lang.")
+        .unwrap_or(curr_type_label);
+
+    match curr_type_label {
+        "Integer" | "int" => Some("int"),
+        "Long" | "long" => Some("long"),
+        "Short" | "short" => Some("short"),
+        "Byte" | "byte" => Some("byte"),
+        "Character" | "char" => Some("char"),
+        "Float" | "float" => Some("float"),
+        "Double" | "double" => Some("double"),
+        "Boolean" | "boolean" => Some("bool"),
+        "Void" | "void" => Some("void"),
+        "String" | "CharSequence" => Some("string"),
+        "Object" => Some("object"),
+        _ => None,
+    }
+}
+
+/// Applies `synonym` to the base type and, for parameterized labels, to every type argument
+pub fn merge_type_parts(label: &str, synonym: fn(&str) -> Option<&'static str>) -> String {
+    if let Some(merged) = synonym(label) {
+        return merged.to_string();
+    }
+
+    let (base, args, open, close) = match split_type_args(label, '<', '>') {
+        (base, args) if !args.is_empty() => (base, args, '<', '>'),
+        _ => {
+            let (base, args) = split_type_args(label, '[', ']');
+            (base, args, '[', ']')
+        }
+    };
+
+    if args.is_empty() {
+        return label.to_string();
+    }
+
+    let args: Vec<String> = args.iter().map(|a| merge_type_parts(a, synonym)).collect();
+    format!(
+        "{}{}{}{}",
+        synonym(base).unwrap_or(base),
+        open,
+        args.join(", "),
+        close
+    )
+}
+
+/// Chain of increasingly coarse labels for `label`, ending in `object`, e.g. `app.models.User` ->
+/// `models.User` -> `User` -> `object`
+pub fn label_hierarchy(label: &str) -> Vec<String> {
+    let mut chain = vec![label.to_string()];
+    let mut curr = label;
+
+    // drop type arguments, `promise<string>` -> `promise`
+    for (open, close) in [('<', '>'), ('[', ']')] {
+        let (base, args) = split_type_args(curr, open, close);
+        if !args.is_empty() {
+            curr = base;
+            chain.push(curr.to_string());
+            break;
+        }
+    }
+
+    // drop module and package qualifiers one at a time, `models.User` -> `User`. The members of a
+    // union have their own qualifiers, so unions only fall back to `object`
+    if !curr.contains(" | ") {
+        while let Some(i) = qualifier_end(curr) {
+            curr = &curr[i..];
+            chain.push(curr.to_string());
+        }
+    }
+
+    if curr != "object" {
+        chain.push("object".to_string());
+    }
+
+    chain
+}
+
+/// End of the first module or package qualifier of a label, `None` if nothing follows it
+fn qualifier_end(label: &str) -> Option<usize> {
+    let i_path = memmem::find(label.as_bytes(), "::".as_bytes()).map(|i| i + 2);
+    let i_dot = memchr::memchr(b'.', label.as_bytes()).map(|i| i + 1);
+    let i = match (i_path, i_dot) {
+        (Some(p), Some(d)) => p.min(d),
+        (p, d) => p.or(d)?,
+    };
+    (i < label.len()).then_some(i)
+}
+
+/// Maps every label to itself or, if it is under `threshold`, to its nearest ancestor that meets the threshold.
+/// Labels without such an ancestor are omitted from the returned mapping.
+pub fn resolve_label_hierarchy(
+    class_counts: &HashMap<String, usize>,
+    threshold: usize,
+) -> HashMap<String, String> {
+    let mut counts = class_counts.clone();
+    let mut parents: HashMap<String, String> = HashMap::new();
+
+    // fold rare labels into their parent, deepest labels first so that a parent
+    // collects all of its children before being considered rare itself
+    loop {
+        let rare: Vec<(String, Vec<String>)> = counts
+            .iter()
+            .filter(|(_, &c)| c < threshold)
+            .map(|(l, _)| (l.to_owned(), label_hierarchy(l)))
+            .filter(|(_, chain)| chain.len() > 1)
+            .collect();
+
+        let max_depth = match rare.iter().map(|(_, chain)| chain.len()).max() {
+            Some(d) => d,
+            None => break,
+        };
+
+        for (label, chain) in rare.into_iter().filter(|(_, c)| c.len() == max_depth) {
+            let c = counts.remove(&label).unwrap_or(0);
+            *counts.entry(chain[1].to_owned()).or_insert(0) += c;
+            parents.insert(label, chain[1].to_owned());
+        }
+    }
+
+    let mut mapping = HashMap::with_capacity(class_counts.len());
+    for label in class_counts.keys() {
+        let mut curr = label;
+        while let Some(parent) = parents.get(curr) {
+            curr = parent;
+        }
+
+        if counts.get(curr).unwrap_or(&0) >= &threshold {
+            mapping.insert(label.to_owned(), curr.to_owned());
+        }
+    }
+
+    mapping
+}
+
+/// Number of samples to keep per class when capping the dataset at `n` samples while maintaining
+/// the distribution of observed classes. Rare classes are served first and keep at least `threshold` samples.
+pub fn sample_quotas(
+    class_counts: &HashMap<String, usize>,
+    threshold: usize,
+    n: usize,
+) -> HashMap<String, usize> {
+    let mut count_vec: Vec<_> = class_counts
+        .iter()
+        .filter(|(_, &c)| c >= threshold)
+        .collect();
+    count_vec.sort_by(|a, b| b.1.cmp(a.1));
+    let total: usize = count_vec.iter().map(|(_, &c)| c).sum();
+
+    let mut quotas = HashMap::new();
+    let mut remaining = n;
+    for (class, &count) in count_vec.into_iter().rev() {
+        if remaining == 0 {
+            break;
+        }
+
+        let max_count = max(count * n / total, threshold);
+        let quota = min(min(count, max_count + 1), remaining);
+        quotas.insert(class.to_owned(), quota);
+        remaining -= quota;
+    }
+
+    quotas
+}
+
+/// Writes the label chain of every class in the final dataset to `class_hierarchy.json`
+pub fn persist_hierarchy<'a>(output_dir: &str, classes: impl Iterator<Item = &'a String>) {
+    let hierarchy: BTreeMap<&String, Vec<String>> =
+        classes.map(|c| (c, label_hierarchy(c))).collect();
+
+    let file = File::create(Path::new(output_dir).join("class_hierarchy.json"))
+        .expect("Failed to open hierarchy file");
+    serde_json::to_writer_pretty(file, &hierarchy).expect("Failed to write hierarchy file");
 }
 
 #[inline(always)]
 pub fn clean_method_name(parser: &Parser, mut name: &str) -> Option<String> {
+    if parser.lang == Language::Java {
+        return clean_java_method_name(parser, name);
+    }
+
     if name.is_empty()
         || parser.finder_eq.find(name.as_bytes()).is_some()
         || parser.finder_newline.find(name.as_bytes()).is_some()
@@ -254,33 +783,29 @@ pub fn clean_method_name(parser: &Parser, mut name: &str) -> Option<String> {
             if name.starts_with("(") {
                 match parser.finder_recv_q.find(name.as_bytes()) {
                     Some(i) => name = &name[i + 3..],
-                    None => match parser.finder_recv.find(name.as_bytes()) {
-                        Some(i) => name = &name[i + 2..],
-                        None => {}
-                    },
+                    None => {
+                        if let Some(i) = parser.finder_recv.find(name.as_bytes()) {
+                            name = &name[i + 2..]
+                        }
+                    }
                 }
             }
 
             // limit the total length
-            if name.len() > 48 {
+            if name.len() > parser.max_name_len {
                 // remove type assertions
-                match parser.finder_as.find(name.as_bytes()) {
-                    Some(i) => {
-                        name = &name[..i];
-                        match memmem::find(name.as_bytes(), "(".as_bytes()) {
-                            Some(i) => name = &name[..i],
-                            None => {}
-                        }
+                if let Some(i) = parser.finder_as.find(name.as_bytes()) {
+                    name = &name[..i];
+                    if let Some(i) = memmem::find(name.as_bytes(), "(".as_bytes()) {
+                        name = &name[..i]
                     }
-                    None => {}
                 }
 
-                if name.len() > 48 {
+                if name.len() > parser.max_name_len {
                     // remove arguments
                     if name.ends_with(")") {
-                        match memmem::find(name.as_bytes(), "(".as_bytes()) {
-                            Some(i) => name = &name[..i],
-                            None => {}
+                        if let Some(i) = memmem::find(name.as_bytes(), "(".as_bytes()) {
+                            name = &name[..i]
                         }
                     }
                 }
@@ -296,34 +821,88 @@ pub fn clean_method_name(parser: &Parser, mut name: &str) -> Option<String> {
 
 /// Create full feature vector from raw data in order to be fed into an LLM
 pub fn assemble(
+    template: &FeatureTemplate,
     obj: &ObjSlice,
-    calls: &Vec<String>,
-    arg_tos: &Vec<String>,
+    calls: &[String],
+    arg_tos: &[String],
+    return_types: &[String],
     language: &Option<String>,
+    call_order: CallOrder,
 ) -> String {
-    let call_names = if calls.len() > 0 {
-        format!(" Calls: {} ;", calls.join(", "))
-    } else {
-        "".to_string()
-    };
+    render_fields(
+        template,
+        FeatureFields {
+            variable: &obj.name,
+            scope: &obj.scope,
+            calls,
+            arg_tos,
+            language: language.as_deref(),
+            return_types,
+            literal_kind: obj.literal_kind,
+            siblings: &obj.siblings,
+            subtokens: &[],
+        },
+        call_order,
+    )
+}
 
-    let arg_names = if arg_tos.len() > 0 {
-        format!(" Argument to: {} ;", arg_tos.join(", "))
-    } else {
-        "".to_string()
-    };
+/// Renders the feature of a sample from its values, same as `assemble` for the original values
+pub fn render(
+    template: &FeatureTemplate,
+    values: &FeatureValues,
+    language: &Option<String>,
+    call_order: CallOrder,
+) -> String {
+    render_fields(
+        template,
+        FeatureFields {
+            variable: &values.variable,
+            scope: &values.scope,
+            calls: &values.calls,
+            arg_tos: &values.arg_tos,
+            language: language.as_deref(),
+            return_types: &values.return_types,
+            literal_kind: values.literal_kind.as_deref(),
+            siblings: &values.siblings,
+            subtokens: &[],
+        },
+        call_order,
+    )
+}
 
-    let lang = if let Some(l) = language {
-        format!(" Language: {} ;", l)
+fn render_fields(
+    template: &FeatureTemplate,
+    fields: FeatureFields,
+    call_order: CallOrder,
+) -> String {
+    let subtokens: Vec<String> = if template.uses(Field::Subtokens) {
+        subtokens(fields.variable)
+            .into_iter()
+            .chain(subtokens(fields.scope))
+            .chain(
+                fields
+                    .calls
+                    .iter()
+                    .chain(fields.arg_tos)
+                    .flat_map(|c| subtokens(split_count(c, call_order).0)),
+            )
+            .unique()
+            .collect()
     } else {
-        "".to_string()
+        Vec::new()
     };
 
-    let mut feat_vec = format!(
-        "Variable: {} ; Scope: {} ;{}{}{}",
-        obj.name, obj.scope, call_names, arg_names, lang
-    )
-    .replace(&['\"', '\\', '\'', '\n', '\t', '\r'][..], "");
+    let language = fields.language;
+    let feat_vec = template.render(&FeatureFields {
+        subtokens: &subtokens,
+        ..fields
+    });
+    clean_feature(&feat_vec, language)
+}
+
+/// Removes quotes and control characters, and the `<module>.` prefix of Python names
+pub fn clean_feature(feature: &str, language: Option<&str>) -> String {
+    let mut feat_vec = feature.replace(&['\"', '\\', '\'', '\n', '\t', '\r'][..], "");
 
     if let Some(l) = language {
         if l.to_lowercase().eq("python") {
@@ -334,6 +913,494 @@ pub fn assemble(
     feat_vec
 }
 
+/// What happens to objects that are literals
+#[derive(clap::ValueEnum, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LiteralPolicy {
+    #[default]
+    Keep,
+    Drop,
+    /// Keep a fixed fraction of the literal objects
+    Cap,
+}
+
+/// Whether the code is an unsigned number literal: decimal with optional fraction and exponent,
+/// or hexadecimal, octal and binary after `0x`, `0o` and `0b`, each with an optional type suffix
+fn is_number_literal(code: &str) -> bool {
+    let digits_of = |s: &str, radix: u32| {
+        s.starts_with(|c: char| c.is_digit(radix))
+            && s.chars().all(|c| c.is_digit(radix) || c == '_')
+    };
+    let code = code.strip_suffix(['n', 'L', 'l', 'j', 'J']).unwrap_or(code);
+
+    let prefix = code.get(..2).map(str::to_ascii_lowercase);
+    let radix = match prefix.as_deref() {
+        Some("0x") => Some(16),
+        Some("0o") => Some(8),
+        Some("0b") => Some(2),
+        _ => None,
+    };
+    if let Some(radix) = radix {
+        return digits_of(&code[2..], radix);
+    }
+
+    // float suffixes of Java and C#
+    let code = code
+        .strip_suffix(['f', 'F', 'd', 'D', 'm', 'M'])
+        .unwrap_or(code);
+    let (mantissa, exponent) = match code.find(['e', 'E']) {
+        Some(i) => (&code[..i], Some(&code[i + 1..])),
+        None => (code, None),
+    };
+    let mantissa_ok = match mantissa.split_once('.') {
+        Some((int, frac)) => digits_of(int, 10) && (frac.is_empty() || digits_of(frac, 10)),
+        None => digits_of(mantissa, 10),
+    };
+    let exponent_ok =
+        exponent.is_none_or(|e| digits_of(e.strip_prefix(['+', '-']).unwrap_or(e), 10));
+    mantissa_ok && exponent_ok
+}
+
+/// Kind of a literal expression, `None` if the code is not a literal
+pub fn literal_kind(code: &str) -> Option<&'static str> {
+    let code = code.trim();
+    let first = code.chars().next()?;
+
+    if matches!(first, '"' | '\'' | '`') {
+        return Some("string");
+    }
+    match code {
+        "true" | "false" | "True" | "False" => return Some("boolean"),
+        "null" | "undefined" | "None" | "nil" => return Some("null"),
+        _ => {}
+    }
+
+    if is_number_literal(code.strip_prefix('-').unwrap_or(code)) {
+        return Some("number");
+    }
+
+    match first {
+        '[' => Some("array"),
+        '{' => Some("object"),
+        '/' if code.len() > 1 && code[1..].contains('/') => Some("regex"),
+        _ => None,
+    }
+}
+
+/// Deterministic position of the given parts on the unit interval, stable across runs
+pub fn stable_fraction(parts: &[&str]) -> f64 {
+    let mut hasher = blake3::Hasher::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update(&[0]);
+    }
+    let digest = hasher.finalize();
+    u64::from_le_bytes(digest.as_bytes()[..8].try_into().unwrap()) as f64 / u64::MAX as f64
+}
+
+/// How the usages of an object are listed in the features
+#[derive(clap::ValueEnum, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CallOrder {
+    /// Each usage once, in order of its first occurrence
+    #[default]
+    Set,
+    /// Original order of the slice, repetitions collapsed into `name xN`
+    Sequence,
+}
+
+/// Names in their original order with consecutive repetitions collapsed into `name xN`
+pub fn run_lengths(names: &[String]) -> Vec<String> {
+    names
+        .iter()
+        .dedup_with_count()
+        .map(|(n, name)| match n {
+            1 => name.to_owned(),
+            _ => format!("{} x{}", name, n),
+        })
+        .collect()
+}
+
+/// Name and repetitions of a usage entry, `push x3` -> (`push`, 3). Only entries of
+/// `CallOrder::Sequence` carry repetitions, other entries are taken as they are.
+pub fn split_count(entry: &str, call_order: CallOrder) -> (&str, usize) {
+    if call_order != CallOrder::Sequence {
+        return (entry, 1);
+    }
+    entry
+        .rsplit_once(" x")
+        .and_then(|(name, n)| match n.parse::<usize>() {
+            Ok(count) if count > 1 && n.bytes().all(|b| b.is_ascii_digit()) => Some((name, count)),
+            _ => None,
+        })
+        .unwrap_or((entry, 1))
+}
+
+/// Number of usages the entries of a feature stand for
+pub fn num_occurrences<S: AsRef<str>>(entries: &[S], call_order: CallOrder) -> usize {
+    entries
+        .iter()
+        .map(|e| split_count(e.as_ref(), call_order).1)
+        .sum()
+}
+
+/// Number of usages of an object, as checked against the usage bounds and kept as `num_usages`
+pub fn num_usages<S: AsRef<str>>(calls: &[S], arg_tos: &[S], call_order: CallOrder) -> usize {
+    num_occurrences(calls, call_order) + num_occurrences(arg_tos, call_order)
+}
+
+/// Where the sub-tokens of identifiers end up in the features
+#[derive(clap::ValueEnum, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SubtokenMode {
+    /// Keep identifiers as they are
+    #[default]
+    Off,
+    /// Replace identifiers with their sub-tokens
+    Inline,
+    /// Keep identifiers and add their sub-tokens as a separate field
+    Field,
+}
+
+/// Splits an identifier into lowercase sub-tokens at camelCase, snake_case, digit and punctuation
+/// boundaries, `getHTTPResponse2` -> `get`, `http`, `response`, `2`
+pub fn subtokens(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+
+    for (i, c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !token.is_empty() {
+                tokens.push(std::mem::take(&mut token));
+            }
+            continue;
+        }
+
+        if let Some(prev) = chars[..i].last().filter(|p| p.is_alphanumeric()) {
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            let boundary = (prev.is_lowercase() && c.is_uppercase())
+                || (prev.is_numeric() != c.is_numeric())
+                // last capital of an acronym starts the next word, `HTTPResponse`
+                || (prev.is_uppercase() && c.is_uppercase() && next_lower);
+            if boundary && !token.is_empty() {
+                tokens.push(std::mem::take(&mut token));
+            }
+        }
+        token.extend(c.to_lowercase());
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+/// Identifier replaced with its sub-tokens separated by spaces
+pub fn subtokenize(name: &str) -> String {
+    subtokens(name).join(" ")
+}
+
+/// Structured counterpart of `assemble`, `arg_positions` holds the argument positions per call
+pub fn feature_record(
+    obj: &ObjSlice,
+    calls: &[String],
+    arg_tos: &[String],
+    arg_positions: &HashMap<String, Vec<i32>>,
+    language: &Option<String>,
+    call_order: CallOrder,
+) -> FeatureRecord {
+    let is_python = language
+        .as_ref()
+        .is_some_and(|l| l.to_lowercase().eq("python"));
+
+    FeatureRecord {
+        variable: obj.name.to_owned(),
+        scope: obj
+            .scope
+            .split("::")
+            .map(|c| match is_python {
+                true => c.replace("<module>.", ""),
+                false => c.to_owned(),
+            })
+            .collect(),
+        calls: calls.to_vec(),
+        arg_tos: arg_tos
+            .iter()
+            .flat_map(|call| {
+                arg_positions
+                    .get(split_count(call, call_order).0)
+                    .into_iter()
+                    .flatten()
+                    .map(|position| ArgTo {
+                        call: call.to_owned(),
+                        position: *position,
+                    })
+            })
+            .collect(),
+        defined_by: obj.defined_by.to_owned(),
+        literal: obj.literal,
+        literal_kind: obj.literal_kind.map(String::from),
+        siblings: obj.siblings.clone(),
+        subtokens: None,
+    }
+}
+
+/// How usage lists longer than the upper usage bound are turned into samples
+#[derive(
+    clap::ValueEnum,
+    serde::Serialize,
+    serde::Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum SplitStrategy {
+    /// Consecutive chunks sharing one usage, see `generate_splits`
+    #[default]
+    Contiguous,
+    /// Windows of the upper bound's size, consecutive windows overlap
+    Sliding,
+    /// Seeded random partition into chunks, usages keep their relative order
+    Random,
+    /// No split, only the first usages up to the upper bound are kept
+    Truncate,
+}
+
+/// Parameters of `split_usages`
+#[derive(Clone, Copy, Debug)]
+pub struct SplitConfig {
+    pub strategy: SplitStrategy,
+    /// Maximum number of usages per chunk
+    pub threshold: usize,
+    /// Number of usages shared by consecutive windows of the `Sliding` strategy
+    pub overlap: usize,
+    pub seed: u64,
+}
+
+/// Rough informativeness of a usage about the type of the object: calls invoked on the object
+/// rank above calls it is passed to, assignments and other operators rank last
+pub fn usage_informativeness(name: &str, invoked: bool) -> u8 {
+    let name = name.strip_prefix("<operator>.").unwrap_or(name);
+    if name.starts_with("assignment")
+        || name.starts_with('<')
+        || matches!(
+            name,
+            "fieldAccess" | "indexAccess" | "indirectFieldAccess" | "indirectIndexAccess"
+        )
+    {
+        0
+    } else if invoked {
+        2
+    } else {
+        1
+    }
+}
+
+/// Packs the calls `a` and argument-to calls `b` of one object into chunks whose feature, as
+/// counted by `count`, fits into the budget, most informative usages first. Chunks keep the
+/// original order of their usages. `Truncate` keeps only the first chunk, other strategies start
+/// new chunks until every usage that fits on its own is used.
+///
+/// Counting the whole feature for every usage tried is quadratic, so the tokens of a chunk are
+/// tracked per section instead. The feature is only counted while a section has fewer than two
+/// entries, which also gives the tokens of its separator, and once more for the complete chunk.
+pub fn pack_usages<F>(
+    a: &[String],
+    b: &[String],
+    strategy: SplitStrategy,
+    budget: &TokenBudget,
+    count: F,
+) -> Vec<(Vec<String>, Vec<String>)>
+where
+    F: Fn(&[String], &[String]) -> usize,
+{
+    // stable sort, so usages of equal rank keep their order
+    let mut ranked: Vec<(bool, usize)> = (0..a.len())
+        .map(|i| (false, i))
+        .chain((0..b.len()).map(|i| (true, i)))
+        .collect();
+    let name = |(is_arg, i): (bool, usize)| if is_arg { &b[i] } else { &a[i] };
+    ranked.sort_by_key(|&usage| std::cmp::Reverse(usage_informativeness(name(usage), !usage.0)));
+    // entries follow a space with the default separator
+    let entry_tokens: Vec<usize> = ranked
+        .iter()
+        .map(|&usage| budget.tokenizer.count(&format!(" {}", name(usage))))
+        .collect();
+
+    // usages are referred to by their rank
+    let count_chunk = |chosen: &[usize]| {
+        let mut chosen: Vec<(bool, usize)> = chosen.iter().map(|&r| ranked[r]).collect();
+        chosen.sort();
+        let pick = |arg: bool, list: &[String]| -> Vec<String> {
+            chosen
+                .iter()
+                .filter(|(is_arg, _)| *is_arg == arg)
+                .map(|(_, i)| list[*i].to_owned())
+                .collect()
+        };
+        let (chunk_a, chunk_b) = (pick(false, a), pick(true, b));
+        let tokens = count(&chunk_a, &chunk_b);
+        (chunk_a, chunk_b, tokens)
+    };
+
+    let mut remaining: Vec<usize> = (0..ranked.len()).collect();
+    let mut chunks = Vec::new();
+    while !remaining.is_empty() {
+        let mut chosen = Vec::new();
+        let mut rest = Vec::new();
+        let mut tokens = 0;
+        // entries and separator tokens of the calls and argument-to sections
+        let mut sections = [(0, 0); 2];
+        for usage in remaining {
+            let section = ranked[usage].0 as usize;
+            let (num_entries, separator) = sections[section];
+            chosen.push(usage);
+            // an entry adds at least its own tokens, the feature is only counted if it may fit
+            let estimate = match num_entries {
+                0 | 1 if tokens + entry_tokens[usage] <= budget.max_tokens => {
+                    count_chunk(&chosen).2
+                }
+                0 | 1 => tokens + entry_tokens[usage],
+                _ => tokens + separator + entry_tokens[usage],
+            };
+            if estimate > budget.max_tokens {
+                chosen.pop();
+                rest.push(usage);
+                continue;
+            }
+
+            if num_entries == 1 {
+                sections[section].1 = estimate.saturating_sub(tokens + entry_tokens[usage]);
+            }
+            sections[section].0 += 1;
+            tokens = estimate;
+        }
+
+        // tokens shared by usages, such as sub-tokens, are not estimated
+        let chunk = loop {
+            let (chunk_a, chunk_b, tokens) = count_chunk(&chosen);
+            if tokens <= budget.max_tokens || chosen.is_empty() {
+                break (chunk_a, chunk_b);
+            }
+            rest.extend(chosen.pop());
+        };
+        rest.sort();
+
+        // the rest does not fit on its own
+        if chosen.is_empty() {
+            break;
+        }
+        chunks.push(chunk);
+        if strategy == SplitStrategy::Truncate {
+            break;
+        }
+        remaining = rest;
+    }
+
+    chunks
+}
+
+/// Splits the calls `a` and argument-to calls `b` of one object into chunks according to the
+/// configured strategy. Every strategy but `Truncate` keeps every usage in at least one chunk.
+pub fn split_usages<T>(a: Vec<T>, b: Vec<T>, config: &SplitConfig) -> Vec<(Vec<T>, Vec<T>)>
+where
+    T: Clone + std::hash::Hash,
+{
+    let threshold = config.threshold.max(1);
+    if a.len() + b.len() <= threshold {
+        return vec![(a, b)];
+    }
+
+    match config.strategy {
+        SplitStrategy::Contiguous => generate_splits(a, b, threshold),
+        SplitStrategy::Sliding => sliding_splits(a, b, threshold, config.overlap),
+        SplitStrategy::Random => random_splits(a, b, threshold, config.seed),
+        SplitStrategy::Truncate => {
+            // keep both kinds of usages in proportion to their counts
+            let n = a.len() + b.len();
+            let mut take_a = (threshold * a.len()).div_ceil(n).min(a.len());
+            if take_a == threshold && !b.is_empty() && threshold > 1 {
+                take_a -= 1;
+            }
+            let take_b = (threshold - take_a).min(b.len());
+            vec![(a[..take_a].to_vec(), b[..take_b].to_vec())]
+        }
+    }
+}
+
+/// Maps indices into the concatenation of `a` and `b` back to the two lists
+fn chunk_from_indices<T: Clone>(a: &[T], b: &[T], indices: &[usize]) -> (Vec<T>, Vec<T>) {
+    let chunk_a = indices
+        .iter()
+        .filter(|i| **i < a.len())
+        .map(|i| a[*i].clone())
+        .collect();
+    let chunk_b = indices
+        .iter()
+        .filter(|i| **i >= a.len())
+        .map(|i| b[*i - a.len()].clone())
+        .collect();
+    (chunk_a, chunk_b)
+}
+
+fn sliding_splits<T: Clone>(
+    a: Vec<T>,
+    b: Vec<T>,
+    window: usize,
+    overlap: usize,
+) -> Vec<(Vec<T>, Vec<T>)> {
+    let n = a.len() + b.len();
+    let step = window.saturating_sub(overlap).max(1);
+
+    let mut starts: Vec<usize> = (0..n - window).step_by(step).collect();
+    // the last window is aligned to the end, so the tail is always covered
+    starts.push(n - window);
+
+    starts
+        .into_iter()
+        .map(|start| {
+            let indices: Vec<usize> = (start..start + window).collect();
+            chunk_from_indices(&a, &b, &indices)
+        })
+        .collect()
+}
+
+fn random_splits<T>(a: Vec<T>, b: Vec<T>, threshold: usize, seed: u64) -> Vec<(Vec<T>, Vec<T>)>
+where
+    T: Clone + std::hash::Hash,
+{
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+    use std::hash::{Hash, Hasher};
+
+    // the seed is combined with the usages, so results do not depend on processing order
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    seed.hash(&mut hasher);
+    a.hash(&mut hasher);
+    b.hash(&mut hasher);
+    let mut rng = rand::rngs::StdRng::seed_from_u64(hasher.finish());
+
+    let n = a.len() + b.len();
+    let mut indices: Vec<usize> = (0..n).collect();
+    indices.shuffle(&mut rng);
+
+    let num_chunks = n.div_ceil(threshold);
+    (0..num_chunks)
+        .map(|i| {
+            // near-equal chunk sizes, each at most `threshold`
+            let mut chunk: Vec<usize> =
+                indices[i * n / num_chunks..(i + 1) * n / num_chunks].to_vec();
+            chunk.sort_unstable();
+            chunk_from_indices(&a, &b, &chunk)
+        })
+        .collect()
+}
+
 pub fn generate_splits<T>(a: Vec<T>, b: Vec<T>, threshold: usize) -> Vec<(Vec<T>, Vec<T>)>
 where
     T: Clone,
@@ -343,7 +1410,7 @@ where
         vec![(a, b)]
     } else {
         // determine the number of tuples needed to split the lists
-        let num_tuples = (combined_length + threshold - 1) / threshold;
+        let num_tuples = combined_length.div_ceil(threshold);
 
         let a_len = a.len();
         let b_len = b.len();
@@ -353,10 +1420,10 @@ where
         let mut min_len_b = b_len / num_tuples;
 
         // adjust for cases where one list is smaller than the other
-        if a_len % num_tuples != 0 {
+        if !a_len.is_multiple_of(num_tuples) {
             min_len_a += 1;
         }
-        if b_len % num_tuples != 0 {
+        if !b_len.is_multiple_of(num_tuples) {
             min_len_b += 1;
         }
 
@@ -374,26 +1441,26 @@ where
 
         // re-use the first element of the smaller list if necessary
         if a_len < num_tuples && a_len > 0 {
-            for i in a_len..num_tuples {
-                splits[i] = (a[0..1].to_vec(), (&splits[i].1).to_vec());
+            for split in splits.iter_mut().skip(a_len) {
+                split.0 = a[0..1].to_vec();
             }
         }
         if b_len < num_tuples && b_len > 0 {
-            for i in b_len..num_tuples {
-                splits[i] = ((&splits[i].0).to_vec(), b[0..1].to_vec());
+            for split in splits.iter_mut().skip(b_len) {
+                split.1 = b[0..1].to_vec();
             }
         }
 
         // make sure one element is the same for all tuples
         if a_len > b_len {
             let el = &a[0];
-            for i in 1..num_tuples {
-                splits[i].0.push(el.clone());
+            for split in splits.iter_mut().skip(1) {
+                split.0.push(el.clone());
             }
         } else {
             let el = &b[0];
-            for i in 1..num_tuples {
-                splits[i].1.push(el.clone());
+            for split in splits.iter_mut().skip(1) {
+                split.1.push(el.clone());
             }
         }
 
@@ -416,7 +1483,7 @@ pub fn extract_func_name(full_qualified_name: &str) -> String {
     }
 
     if nested_namespaces[i].starts_with("program") {
-        format!("{}", file_name)
+        file_name.to_string()
     } else if !(nested_namespaces.len() > 3
         && i != 1
         && nested_namespaces[i - 1].ne("program")
@@ -424,7 +1491,7 @@ pub fn extract_func_name(full_qualified_name: &str) -> String {
         && !nested_namespaces[i - 1].starts_with("<init>")
         && !nested_namespaces[i].starts_with("program"))
     {
-        format!("{}::{}", file_name, nested_namespaces[i].to_string())
+        format!("{}::{}", file_name, nested_namespaces[i])
     } else {
         format!(
             "{}::{}::{}",
@@ -435,49 +1502,501 @@ pub fn extract_func_name(full_qualified_name: &str) -> String {
     }
 }
 
-pub fn persist_to_disk(data: Vec<(String, String, usize)>) {
-    let t0 = std::time::Instant::now();
+/// Extract `Class::method` from a JVM method full name used as scope, e.g. `com.foo.Bar.baz:void(int)`
+pub fn extract_java_func_name(full_qualified_name: &str) -> String {
+    match parse_java_signature(full_qualified_name) {
+        Some(sig) => format!(
+            "{}::{}",
+            shorten_java_name(&sig.class.replace('$', ".")),
+            sig.method
+        ),
+        None => extract_func_name(full_qualified_name),
+    }
+}
+
+/// Scope name of a fully qualified method name, as used in the features
+pub fn extract_scope_name(lang: Language, full_qualified_name: &str) -> String {
+    if lang == Language::Java {
+        extract_java_func_name(full_qualified_name)
+    } else {
+        extract_func_name(full_qualified_name)
+    }
+}
+
+/// Streams features and labels into the `feature_vec.json` and `class_label_vec.json` arrays
+pub struct DatasetWriter {
+    feat_file: BufWriter<File>,
+    label_file: BufWriter<File>,
+    pub num_samples: usize,
+}
+
+impl DatasetWriter {
+    /// Writes `feature_vec{suffix}.json` and `class_label_vec{suffix}.json`
+    pub fn create(output_dir: &str, suffix: &str) -> Self {
+        let mut feat_file = BufWriter::new(
+            File::create(Path::new(output_dir).join(format!("feature_vec{}.json", suffix)))
+                .expect("Failed to open feature file"),
+        );
+        let mut label_file = BufWriter::new(
+            File::create(Path::new(output_dir).join(format!("class_label_vec{}.json", suffix)))
+                .expect("Failed to open label file"),
+        );
+
+        feat_file
+            .write_all("[\n".as_bytes())
+            .expect("Failed to write preamble to feature file");
+        label_file
+            .write_all("[\n".as_bytes())
+            .expect("Failed to write preamble to label file");
+
+        DatasetWriter {
+            feat_file,
+            label_file,
+            num_samples: 0,
+        }
+    }
+
+    pub fn write(&mut self, sample: &Sample) {
+        let sep = if self.num_samples > 0 { ",\n" } else { "" };
+
+        write!(self.feat_file, "{}\"{}\"", sep, sample.feature)
+            .expect("Failed to write data to feature file");
+        write!(self.label_file, "{}\"{}\"", sep, sample.label)
+            .expect("Failed to write data to label file");
+        self.num_samples += 1;
+    }
+
+    pub fn finish(self) {
+        for (mut file, name) in [(self.feat_file, "feature"), (self.label_file, "label")] {
+            file.write_all("\n]".as_bytes())
+                .unwrap_or_else(|_| panic!("Failed to write to {} file", name));
+            file.into_inner()
+                .unwrap_or_else(|_| panic!("Failed to flush {} file", name))
+                .sync_all()
+                .unwrap_or_else(|_| panic!("Failed to flush {} file", name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::Tokenizer;
+
+    fn java_parser(shorten_packages: bool) -> Parser<'static> {
+        let mut parser = Parser::new(&Some("java".to_string()));
+        parser.shorten_packages = shorten_packages;
+        parser
+    }
+
+    #[test]
+    fn java_signature_is_split_into_parts() {
+        let sig = parse_java_signature("java.util.Map.put:java.lang.Object(java.lang.Object,int)");
+        assert_eq!(
+            sig,
+            Some(JavaSignature {
+                class: "java.util.Map",
+                method: "put",
+                return_type: "java.lang.Object",
+                param_types: vec!["java.lang.Object", "int"],
+            })
+        );
 
-    let mut features = Vec::with_capacity(data.len());
-    let mut labels = Vec::with_capacity(data.len());
+        let sig = parse_java_signature("Foo.run:void()").unwrap();
+        assert_eq!(
+            (sig.class, sig.method, sig.return_type),
+            ("Foo", "run", "void")
+        );
+        assert!(sig.param_types.is_empty());
 
-    for (a, b, _) in data.iter() {
-        features.push(format!("\"{}\"", a.to_owned()));
-        labels.push(format!("\"{}\"", b.to_owned()));
+        assert_eq!(parse_java_signature("no_signature"), None);
     }
 
-    let feat_buf = features.join(",\n");
-    let label_buf = labels.join(",\n");
+    #[test]
+    fn java_types_are_normalized() {
+        let parser = java_parser(false);
+        assert_eq!(
+            clean_java_type(&parser, "java.util.List<java.lang.String>", 0),
+            "java.util.List"
+        );
+        assert_eq!(
+            clean_java_type(&parser, "java.util.Map$Entry", 0),
+            "java.util.Map.Entry"
+        );
+        assert_eq!(clean_java_type(&parser, "int[]", 0), "Array");
+        assert_eq!(
+            clean_java_type(&parser, "<unresolvedNamespace>.com.acme.User", 0),
+            "com.acme.User"
+        );
 
-    let mut feat_file = File::create("./feature_vec.json").expect("Failed to open feature file");
-    let mut label_file = File::create("./class_label_vec.json").expect("Failed to open label file");
+        let parser = java_parser(true);
+        assert_eq!(
+            clean_java_type(&parser, "java.util.Map$Entry", 0),
+            "Map.Entry"
+        );
+        assert_eq!(shorten_java_name("com.acme.model.User"), "User");
+        assert_eq!(shorten_java_name("lowercase.only"), "only");
+    }
+
+    #[test]
+    fn java_method_names_keep_class_and_method() {
+        let parser = java_parser(false);
+        assert_eq!(
+            clean_java_method_name(&parser, "java.util.List.add:boolean(java.lang.Object)"),
+            Some("java.util.List.add".to_string())
+        );
+        assert_eq!(
+            clean_java_method_name(&parser, "<operator>.assignment"),
+            Some("assignment".to_string())
+        );
+        assert_eq!(clean_java_method_name(&parser, ""), None);
 
-    feat_file
-        .write("[\n".as_bytes())
-        .expect("Failed to write preamble to feature file");
-    label_file
-        .write("[\n".as_bytes())
-        .expect("Failed to write preamble to label file");
+        let parser = java_parser(true);
+        assert_eq!(
+            clean_java_method_name(&parser, "java.util.Map$Entry.getKey:java.lang.Object()"),
+            Some("Map.Entry.getKey".to_string())
+        );
+        assert_eq!(
+            extract_java_func_name("com.acme.Service$Inner.run:void(int)"),
+            "Service.Inner::run"
+        );
+    }
 
-    feat_file
-        .write_all(feat_buf.as_bytes())
-        .expect("Failed to write data to feature file");
-    label_file
-        .write_all(label_buf.as_bytes())
-        .expect("Failed to write data to label file");
+    #[test]
+    fn java_boxed_types_merge_with_primitives() {
+        let parser = java_parser(false);
+        assert_eq!(merge_synonyms(&parser, "java.lang.Integer"), "int");
+        assert_eq!(merge_synonyms(&parser, "Boolean"), "bool");
+        assert_eq!(merge_synonyms(&parser, "java.lang.String"), "string");
+        assert_eq!(merge_synonyms(&parser, "com.acme.User"), "com.acme.User");
+    }
 
-    feat_file
-        .write("\n]".as_bytes())
-        .expect("Failed to write to feature file");
-    label_file
-        .write("\n]".as_bytes())
-        .expect("Failed to write to label file");
+    #[test]
+    fn hierarchy_drops_one_qualifier_at_a_time() {
+        assert_eq!(
+            label_hierarchy("a.b.models.User"),
+            [
+                "a.b.models.User",
+                "b.models.User",
+                "models.User",
+                "User",
+                "object"
+            ]
+        );
+        assert_eq!(
+            label_hierarchy("Promise<models.User>"),
+            ["Promise<models.User>", "Promise", "object"]
+        );
+        assert_eq!(
+            label_hierarchy("<export>::utils.Config"),
+            ["<export>::utils.Config", "utils.Config", "Config", "object"]
+        );
+        assert_eq!(label_hierarchy("int | str"), ["int | str", "object"]);
+        assert_eq!(label_hierarchy("object"), ["object"]);
+    }
 
-    feat_file.sync_all().expect("Failed to flush feature file");
-    label_file.sync_all().expect("Failed to flush label file");
+    #[test]
+    fn rare_labels_fold_into_nearest_frequent_ancestor() {
+        let counts: HashMap<String, usize> = [
+            ("app.models.User", 2),
+            ("lib.models.User", 2),
+            ("models.Admin", 1),
+            ("string", 10),
+            ("Foo", 1),
+        ]
+        .into_iter()
+        .map(|(l, c)| (l.to_string(), c))
+        .collect();
 
-    println!(
-        "[i] Persisting vectors to disk took {:.2} sec",
-        t0.elapsed().as_secs_f32()
-    );
+        let mapping = resolve_label_hierarchy(&counts, 4);
+        assert_eq!(mapping["app.models.User"], "models.User");
+        assert_eq!(mapping["lib.models.User"], "models.User");
+        assert_eq!(mapping["string"], "string");
+        // `models.Admin` and `Foo` only reach `object`, which stays under the threshold
+        assert!(!mapping.contains_key("models.Admin"));
+        assert!(!mapping.contains_key("Foo"));
+    }
+
+    #[test]
+    fn granularity_depths() {
+        assert_eq!(LabelGranularity::Base.depth(), 0);
+        assert_eq!(LabelGranularity::OneLevel.depth(), 1);
+        assert_eq!(LabelGranularity::Full.depth(), usize::MAX);
+    }
+
+    #[test]
+    fn typescript_generics_follow_granularity() {
+        let mut parser = Parser::new(&None);
+        let name = "Promise<Map<string, User[]>>";
+        assert_eq!(type_label(&parser, name), "Promise");
+        assert_eq!(type_label(&parser, "User[]"), "Array");
+
+        parser.label_granularity = LabelGranularity::OneLevel;
+        assert_eq!(type_label(&parser, name), "Promise<Map>");
+        assert_eq!(type_label(&parser, "User[]"), "Array<User>");
+
+        parser.label_granularity = LabelGranularity::Full;
+        assert_eq!(
+            type_label(&parser, name),
+            "Promise<Map<string, Array<User>>>"
+        );
+    }
+
+    #[test]
+    fn java_generics_follow_granularity() {
+        let mut parser = java_parser(true);
+        let name = "java.util.Map<java.lang.String,java.util.List<com.acme.User>>";
+        assert_eq!(type_label(&parser, name), "Map");
+
+        parser.label_granularity = LabelGranularity::OneLevel;
+        assert_eq!(type_label(&parser, name), "Map<String, List>");
+
+        parser.label_granularity = LabelGranularity::Full;
+        assert_eq!(type_label(&parser, name), "Map<String, List<User>>");
+    }
+
+    fn python_parser(granularity: LabelGranularity) -> Parser<'static> {
+        let mut parser = Parser::new(&Some("python".to_string()));
+        parser.label_granularity = granularity;
+        parser
+    }
+
+    #[test]
+    fn python_optional_unwraps_to_member() {
+        let parser = python_parser(LabelGranularity::Base);
+        assert_eq!(type_label(&parser, "Optional[str]"), "str");
+        assert_eq!(type_label(&parser, "typing.Optional[int]"), "int");
+        assert_eq!(type_label(&parser, "str | None"), "str");
+        assert_eq!(type_label(&parser, "None"), "None");
+    }
+
+    #[test]
+    fn python_unions_get_sorted_label() {
+        let parser = python_parser(LabelGranularity::Base);
+        assert_eq!(type_label(&parser, "Union[str, int]"), "int | str");
+        assert_eq!(type_label(&parser, "str | int"), "int | str");
+        assert_eq!(type_label(&parser, "int | str | int"), "int | str");
+        assert_eq!(type_label(&parser, "Union[str, int, None]"), "int | str");
+        assert_eq!(
+            type_label(&parser, "Optional[Union[bytes, str]]"),
+            "bytes | str"
+        );
+    }
+
+    #[test]
+    fn python_aliases_are_canonical() {
+        let parser = python_parser(LabelGranularity::Base);
+        assert_eq!(type_label(&parser, "builtins.str"), "str");
+        assert_eq!(type_label(&parser, "typing.List"), "list");
+        assert_eq!(type_label(&parser, "typing.Dict[str, int]"), "dict");
+        assert_eq!(type_label(&parser, "typing.Text"), "str");
+        assert_eq!(type_label(&parser, "builtins.NoneType"), "None");
+    }
+
+    #[test]
+    fn python_subscripted_generics_follow_granularity() {
+        let parser = python_parser(LabelGranularity::Base);
+        assert_eq!(type_label(&parser, "List[Dict[str, int]]"), "list");
+
+        let parser = python_parser(LabelGranularity::OneLevel);
+        assert_eq!(type_label(&parser, "List[Dict[str, int]]"), "list[dict]");
+        assert_eq!(
+            type_label(&parser, "Dict[str, Union[int, str]]"),
+            "dict[str, int | str]"
+        );
+
+        let parser = python_parser(LabelGranularity::Full);
+        assert_eq!(
+            type_label(&parser, "typing.List[typing.Dict[str, builtins.int]]"),
+            "list[dict[str, int]]"
+        );
+    }
+
+    #[test]
+    fn subtokens_split_case_and_digits() {
+        assert_eq!(
+            subtokens("getHTTPResponse2"),
+            vec!["get", "http", "response", "2"]
+        );
+        assert_eq!(subtokens("XMLParser"), vec!["xml", "parser"]);
+        assert_eq!(subtokens("parseURL"), vec!["parse", "url"]);
+        assert_eq!(subtokens("base64Encode"), vec!["base", "64", "encode"]);
+        assert_eq!(subtokens("user"), vec!["user"]);
+    }
+
+    #[test]
+    fn subtokens_split_on_punctuation() {
+        assert_eq!(subtokens("max_retry_count"), vec!["max", "retry", "count"]);
+        assert_eq!(subtokens("__init__"), vec!["init"]);
+        assert_eq!(
+            subtokens("app.ts::loadUser"),
+            vec!["app", "ts", "load", "user"]
+        );
+        assert_eq!(subtokens("$el"), vec!["el"]);
+        assert_eq!(subtokenize("MAX_SIZE"), "max size");
+    }
+
+    #[test]
+    fn subtokens_of_empty_names() {
+        assert!(subtokens("").is_empty());
+        assert!(subtokens("_").is_empty());
+        assert!(subtokens("::").is_empty());
+    }
+
+    #[test]
+    fn literal_kinds() {
+        assert_eq!(literal_kind("\"text\""), Some("string"));
+        assert_eq!(literal_kind("'c'"), Some("string"));
+        assert_eq!(literal_kind("`${a}`"), Some("string"));
+        assert_eq!(literal_kind("true"), Some("boolean"));
+        assert_eq!(literal_kind("False"), Some("boolean"));
+        assert_eq!(literal_kind("null"), Some("null"));
+        assert_eq!(literal_kind("None"), Some("null"));
+        assert_eq!(literal_kind("[1, 2]"), Some("array"));
+        assert_eq!(literal_kind("{ a: 1 }"), Some("object"));
+        assert_eq!(literal_kind("/ab+c/i"), Some("regex"));
+        assert_eq!(literal_kind("a / b"), None);
+        assert_eq!(literal_kind("foo()"), None);
+        assert_eq!(literal_kind(""), None);
+    }
+
+    #[test]
+    fn number_literals() {
+        for code in [
+            "0", "42", "-7", "1_000", "3.14", "1.", "-0.5", "1e10", "1.5E-3", "2e+8", "0x1F",
+            "0XffL", "0o17", "0b1010", "10n", "100L", "1j", "1.5f", "2.0d",
+        ] {
+            assert_eq!(literal_kind(code), Some("number"), "{}", code);
+        }
+    }
+
+    #[test]
+    fn malformed_numbers_are_not_literals() {
+        for code in [
+            "12abc", "1e", "1e+", "1f2", "0x", "0xg1", "0b102", "0o8", "1.2.3", "-", "1-2",
+            "deadbeef", "ff",
+        ] {
+            assert_eq!(literal_kind(code), None, "{}", code);
+        }
+    }
+
+    #[test]
+    fn run_lengths_collapse_consecutive_repetitions() {
+        let names: Vec<String> = ["push", "push", "push", "pop", "push", "len", "len"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        assert_eq!(
+            run_lengths(&names),
+            vec!["push x3", "pop", "push", "len x2"]
+        );
+        assert!(run_lengths(&[]).is_empty());
+    }
+
+    #[test]
+    fn split_count_reads_repetitions_of_sequences() {
+        let order = CallOrder::Sequence;
+        assert_eq!(split_count("push x3", order), ("push", 3));
+        assert_eq!(split_count("push", order), ("push", 1));
+        assert_eq!(split_count("a x b x12", order), ("a x b", 12));
+        // not written by `run_lengths`
+        assert_eq!(split_count("push x1", order), ("push x1", 1));
+        assert_eq!(split_count("push x", order), ("push x", 1));
+        assert_eq!(split_count("push x+3", order), ("push x+3", 1));
+        assert_eq!(num_occurrences(&["push x3", "pop", "len x2"], order), 6);
+    }
+
+    #[test]
+    fn usages_of_sequences_count_repetitions() {
+        let calls = run_lengths(&strings(&["push", "push", "push", "pop"]));
+        let arg_tos = run_lengths(&strings(&["log", "log"]));
+        assert_eq!(calls, strings(&["push x3", "pop"]));
+
+        // bounds are checked against every occurrence, not the collapsed entries
+        assert_eq!(num_usages(&calls, &arg_tos, CallOrder::Sequence), 6);
+        assert_eq!(num_usages(&calls, &arg_tos, CallOrder::Set), 3);
+    }
+
+    #[test]
+    fn split_count_keeps_names_of_sets() {
+        let order = CallOrder::Set;
+        assert_eq!(split_count("rotate x2", order), ("rotate x2", 1));
+        assert_eq!(num_occurrences(&["rotate x2", "scale x10"], order), 2);
+    }
+
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    /// Counts the words of a feature with the default sections, and how often it is called
+    fn word_count<'a>(
+        renders: &'a std::cell::Cell<usize>,
+    ) -> impl Fn(&[String], &[String]) -> usize + 'a {
+        move |calls: &[String], arg_tos: &[String]| {
+            renders.set(renders.get() + 1);
+            let mut feature = "Variable: name ;".to_string();
+            if !calls.is_empty() {
+                feature.push_str(&format!(" Calls: {} ;", calls.join(", ")));
+            }
+            if !arg_tos.is_empty() {
+                feature.push_str(&format!(" Argument to: {} ;", arg_tos.join(", ")));
+            }
+            Tokenizer::Whitespace.count(&feature)
+        }
+    }
+
+    fn budget(max_tokens: usize) -> TokenBudget {
+        TokenBudget {
+            tokenizer: Tokenizer::Whitespace,
+            max_tokens,
+        }
+    }
+
+    #[test]
+    fn packed_usages_keep_informative_usages() {
+        let calls = strings(&[
+            "<operator>.assignment",
+            "trim",
+            "<operator>.fieldAccess",
+            "split",
+        ]);
+        let arg_tos = strings(&["<operator>.indexAccess", "log"]);
+        let renders = std::cell::Cell::new(0);
+
+        // room for three usages besides the headers of both sections
+        let chunks = pack_usages(
+            &calls,
+            &arg_tos,
+            SplitStrategy::Truncate,
+            &budget(3 + 2 + 3 + 3),
+            word_count(&renders),
+        );
+        assert_eq!(
+            chunks,
+            vec![(strings(&["trim", "split"]), strings(&["log"]))]
+        );
+    }
+
+    #[test]
+    fn packed_chunks_fit_without_counting_every_usage() {
+        let calls: Vec<String> = (0..60).map(|i| format!("call{}", i)).collect();
+        let arg_tos: Vec<String> = (0..30).map(|i| format!("arg{}", i)).collect();
+        let renders = std::cell::Cell::new(0);
+        let count = word_count(&renders);
+
+        let chunks = pack_usages(
+            &calls,
+            &arg_tos,
+            SplitStrategy::Contiguous,
+            &budget(20),
+            &count,
+        );
+        let num_renders = renders.get();
+        assert!(chunks.iter().all(|(c, a)| count(c, a) <= 20));
+        // every usage is kept once, calls rank first
+        let (packed_calls, packed_arg_tos): (Vec<_>, Vec<_>) = chunks.iter().cloned().unzip();
+        assert_eq!(packed_calls.concat(), calls);
+        assert_eq!(packed_arg_tos.concat(), arg_tos);
+        assert!(num_renders <= 6 * chunks.len(), "{} renders", num_renders);
+    }
 }
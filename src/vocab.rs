@@ -0,0 +1,232 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::Path;
+
+/// What happens to labels that are missing from the input vocabulary
+#[derive(clap::ValueEnum, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnknownLabels {
+    /// Append them to the vocabulary
+    #[default]
+    Extend,
+    /// Relabel them to the `other` label
+    Other,
+    /// Drop their samples
+    Drop,
+}
+
+/// Labels added to or removed from the vocabulary relative to the input vocabulary
+#[derive(Debug, Default, Serialize)]
+pub struct VocabChanges {
+    pub added: Vec<String>,
+    /// In the input vocabulary but without samples in this dataset, their ids stay reserved
+    pub removed: Vec<String>,
+    /// Not in the input vocabulary and mapped to the `other` label or dropped
+    pub unknown: Vec<String>,
+}
+
+/// Label vocabulary, a label's id is its index. Ids are never reassigned, so datasets built
+/// against the same vocabulary share their label ids.
+pub struct LabelVocab {
+    labels: Vec<String>,
+    ids: HashMap<String, u32>,
+    /// Labels resolved to another label of the vocabulary
+    aliases: HashMap<String, String>,
+}
+
+impl LabelVocab {
+    fn from_labels(labels: Vec<String>) -> Self {
+        let ids = labels
+            .iter()
+            .enumerate()
+            .map(|(i, l)| (l.to_owned(), i as u32))
+            .collect();
+
+        LabelVocab {
+            labels,
+            ids,
+            aliases: HashMap::new(),
+        }
+    }
+
+    fn push(&mut self, label: &str) -> u32 {
+        let id = self.labels.len() as u32;
+        self.labels.push(label.to_owned());
+        self.ids.insert(label.to_owned(), id);
+        id
+    }
+
+    /// Vocabulary of the given classes, ids in lexicographic order
+    pub fn new<'a>(classes: impl Iterator<Item = &'a String>) -> Self {
+        let mut labels: Vec<String> = classes.cloned().collect();
+        labels.sort();
+        Self::from_labels(labels)
+    }
+
+    /// Loads a vocabulary written by `persist` and resolves `classes` against it
+    pub fn load<'a>(
+        path: &str,
+        classes: impl Iterator<Item = &'a String>,
+        policy: UnknownLabels,
+        other_label: &str,
+    ) -> (Self, VocabChanges) {
+        let file = File::open(path).expect("Failed to open label vocabulary");
+        let labels: Vec<String> =
+            serde_json::from_reader(file).expect("Failed to parse label vocabulary");
+        let mut vocab = Self::from_labels(labels);
+        let mut changes = VocabChanges::default();
+
+        let mut classes: Vec<&String> = classes.collect();
+        classes.sort();
+        let present: HashSet<&str> = classes.iter().map(|c| c.as_str()).collect();
+
+        for class in classes {
+            if vocab.ids.contains_key(class) {
+                continue;
+            }
+
+            match policy {
+                UnknownLabels::Extend => {
+                    vocab.push(class);
+                    changes.added.push(class.to_owned());
+                }
+                UnknownLabels::Other => {
+                    if !vocab.ids.contains_key(other_label) {
+                        vocab.push(other_label);
+                        changes.added.push(other_label.to_owned());
+                    }
+                    vocab
+                        .aliases
+                        .insert(class.to_owned(), other_label.to_owned());
+                    changes.unknown.push(class.to_owned());
+                }
+                UnknownLabels::Drop => changes.unknown.push(class.to_owned()),
+            }
+        }
+
+        let resolved: HashSet<&str> = vocab.aliases.values().map(|l| l.as_str()).collect();
+        changes.removed = vocab
+            .labels
+            .iter()
+            .filter(|l| !present.contains(l.as_str()) && !resolved.contains(l.as_str()))
+            .cloned()
+            .collect();
+
+        (vocab, changes)
+    }
+
+    /// Vocabulary label and id of a class, `None` if the class is not part of the vocabulary
+    pub fn resolve<'a>(&'a self, label: &'a str) -> Option<(&'a str, u32)> {
+        let label = self.aliases.get(label).map_or(label, |l| l.as_str());
+        self.ids.get(label).map(|id| (label, *id))
+    }
+
+    /// Labels of the vocabulary, indexed by id
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Writes the vocabulary to `label_vocab.json`, a JSON array of labels indexed by id
+    pub fn persist(&self, output_dir: &str) {
+        let file = File::create(Path::new(output_dir).join("label_vocab.json"))
+            .expect("Failed to open label vocabulary file");
+        serde_json::to_writer_pretty(file, &self.labels)
+            .expect("Failed to write label vocabulary file");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn load(input: &[&str], classes: &[&str], policy: UnknownLabels) -> (LabelVocab, VocabChanges) {
+        let dir = std::env::temp_dir().join(format!("extys-vocab-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = dir.join(format!("{}.json", NEXT.fetch_add(1, Ordering::Relaxed)));
+        std::fs::write(&path, serde_json::to_string(input).unwrap()).unwrap();
+
+        let classes: Vec<String> = classes.iter().map(|c| c.to_string()).collect();
+        LabelVocab::load(path.to_str().unwrap(), classes.iter(), policy, "other")
+    }
+
+    #[test]
+    fn new_vocabulary_is_sorted() {
+        let classes = [
+            "string".to_string(),
+            "Foo".to_string(),
+            "number".to_string(),
+        ];
+        let vocab = LabelVocab::new(classes.iter());
+        assert_eq!(vocab.labels(), &["Foo", "number", "string"]);
+        assert_eq!(vocab.resolve("number"), Some(("number", 1)));
+        assert_eq!(vocab.resolve("bool"), None);
+    }
+
+    #[test]
+    fn loaded_ids_are_kept() {
+        let (vocab, changes) = load(
+            &["string", "Foo", "number"],
+            &["number", "string"],
+            UnknownLabels::Extend,
+        );
+        assert_eq!(vocab.resolve("string"), Some(("string", 0)));
+        assert_eq!(vocab.resolve("number"), Some(("number", 2)));
+        assert!(changes.added.is_empty() && changes.unknown.is_empty());
+        assert_eq!(changes.removed, vec!["Foo"]);
+    }
+
+    #[test]
+    fn unknown_labels_extend_the_vocabulary() {
+        let (vocab, changes) = load(
+            &["string", "Foo"],
+            &["string", "bool", "Bar"],
+            UnknownLabels::Extend,
+        );
+        assert_eq!(vocab.labels(), &["string", "Foo", "Bar", "bool"]);
+        assert_eq!(vocab.resolve("bool"), Some(("bool", 3)));
+        assert_eq!(changes.added, vec!["Bar", "bool"]);
+        assert_eq!(changes.removed, vec!["Foo"]);
+    }
+
+    #[test]
+    fn unknown_labels_map_to_other() {
+        let (vocab, changes) = load(
+            &["string", "Foo"],
+            &["string", "bool", "Bar"],
+            UnknownLabels::Other,
+        );
+        assert_eq!(vocab.labels(), &["string", "Foo", "other"]);
+        assert_eq!(vocab.resolve("bool"), Some(("other", 2)));
+        assert_eq!(vocab.resolve("Bar"), Some(("other", 2)));
+        assert_eq!(vocab.resolve("string"), Some(("string", 0)));
+        assert_eq!(changes.added, vec!["other"]);
+        assert_eq!(changes.unknown, vec!["Bar", "bool"]);
+        assert_eq!(changes.removed, vec!["Foo"]);
+
+        let (vocab, changes) = load(&["other", "string"], &["bool"], UnknownLabels::Other);
+        assert_eq!(vocab.resolve("bool"), Some(("other", 0)));
+        assert!(changes.added.is_empty());
+        assert_eq!(changes.removed, vec!["string"]);
+    }
+
+    #[test]
+    fn unknown_labels_are_dropped() {
+        let (vocab, changes) = load(&["string", "Foo"], &["string", "bool"], UnknownLabels::Drop);
+        assert_eq!(vocab.labels(), &["string", "Foo"]);
+        assert_eq!(vocab.resolve("bool"), None);
+        assert!(changes.added.is_empty());
+        assert_eq!(changes.unknown, vec!["bool"]);
+        assert_eq!(changes.removed, vec!["Foo"]);
+    }
+}